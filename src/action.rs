@@ -1,6 +1,11 @@
+use std::any::{Any, TypeId};
+use std::path::PathBuf;
+
 use sdl2::mouse::MouseButton;
 
-use super::img::Img;
+use super::img::ImgHandle;
+use super::store::{RequestId, StoreId};
+use super::timer::TimerId;
 use sdl2::keyboard::{Scancode};
 
 /// An Action in the Flux pattern sense. Has a type and a payload.
@@ -8,25 +13,239 @@ use sdl2::keyboard::{Scancode};
 /// All communication between the stores happen through these babies. They are sent to the
 /// dispatcher which sends them to every store that wants to hear of actions of their type.
 pub enum Action {
-	AddImgToCanvasAction(u32, u32, Img),
+	/// Hands the renderer an image to paint at the given position in the next `DrawAction`, see
+	/// `Depth` for the order. On the world layers the position is in world coordinates and can be
+	/// negative, on the others it's on the screen.
+	AddImgToCanvasAction(i32, i32, Depth, ImgHandle),
 	DrawAction(bool),
 	UpdateAction,
-	SendFrameAction(u32, u32, u32),
+	SendFrameAction,
 	EndFrameAction,
 	StartAction,
 	MenuAction(MenuSubAction),
+	/// Moves the renderer's camera, see `CameraSubAction`.
+	CameraAction(CameraSubAction),
 	QuitAction,
 	EmptyAction,
-	TestAction(u8),
 	KeyboardAction(Scancode),
+	RemoveStoreAction(StoreId),
+	CancelTimerAction(TimerId),
+	/// An action meant for a single store, see `Envelope`.
+	DirectedAction(Envelope),
+	/// Asks the renderer for the size of the canvas. Send it as a request, the answer is a
+	/// `CanvasSizeAction`.
+	GetCanvasSizeAction,
+	/// Width and height of the canvas in pixels of the game, and the size of such a pixel on the
+	/// screen.
+	CanvasSizeAction(u32, u32, u32),
+	/// Sent by the renderer when the game starts. Tells whether presenting a frame waits for the
+	/// vertical sync, in which case the dispatcher doesn't have to limit the frame rate itself.
+	VsyncAction(bool),
+	/// Sets the time scale of the dispatcher's clock, see `GameClock::set_time_scale`.
+	TimeScaleAction(f64),
+	/// Lets a single frame of game time pass while time is frozen, see `GameClock::step_frame`.
+	StepFrameAction,
+	/// Saves the snapshots of all stores with a save key to a file, see `Dispatcher::save_to`.
+	SaveAction(PathBuf),
+	/// Restores all stores with a save key from a file, see `Dispatcher::load_from`.
+	LoadAction(PathBuf),
+	/// An asset file was changed on disk, see `AssetWatcher`. Carries the path and the new
+	/// contents of the file, which can be turned into an image with `Img::new_from_bytes` or
+	/// `Sprite::reload_from_bytes`.
+	AssetReloadAction(PathBuf, Vec<u8>),
+	/// A store failed to handle an action. Carries the store and its error, see `ErrorPolicy`.
+	StoreErrorAction(StoreId, String),
+	/// A message defined by the game on top of the framework, see `CustomPayload`.
+	CustomAction(Box<CustomPayload>),
+}
+
+impl Action {
+	/// Returns the kind of the action, which is what stores subscribe to.
+	pub fn kind(&self) -> ActionKind {
+		match self {
+			Action::AddImgToCanvasAction(..) => ActionKind::AddImgToCanvasAction,
+			Action::DrawAction(_) => ActionKind::DrawAction,
+			Action::UpdateAction => ActionKind::UpdateAction,
+			Action::SendFrameAction => ActionKind::SendFrameAction,
+			Action::EndFrameAction => ActionKind::EndFrameAction,
+			Action::StartAction => ActionKind::StartAction,
+			Action::MenuAction(_) => ActionKind::MenuAction,
+			Action::CameraAction(_) => ActionKind::CameraAction,
+			Action::QuitAction => ActionKind::QuitAction,
+			Action::EmptyAction => ActionKind::EmptyAction,
+			Action::KeyboardAction(_) => ActionKind::KeyboardAction,
+			Action::RemoveStoreAction(_) => ActionKind::RemoveStoreAction,
+			Action::CancelTimerAction(_) => ActionKind::CancelTimerAction,
+			Action::DirectedAction(_) => ActionKind::DirectedAction,
+			Action::GetCanvasSizeAction => ActionKind::GetCanvasSizeAction,
+			Action::CanvasSizeAction(..) => ActionKind::CanvasSizeAction,
+			Action::VsyncAction(_) => ActionKind::VsyncAction,
+			Action::TimeScaleAction(_) => ActionKind::TimeScaleAction,
+			Action::StepFrameAction => ActionKind::StepFrameAction,
+			Action::SaveAction(_) => ActionKind::SaveAction,
+			Action::LoadAction(_) => ActionKind::LoadAction,
+			Action::AssetReloadAction(..) => ActionKind::AssetReloadAction,
+			Action::StoreErrorAction(..) => ActionKind::StoreErrorAction,
+			Action::CustomAction(payload) => ActionKind::CustomAction((**payload).as_any().type_id()),
+		}
+	}
+
+	/// Wraps a game-defined payload in an action.
+	pub fn custom<T: CustomPayload>(payload: T) -> Self {
+		Action::CustomAction(Box::new(payload))
+	}
+
+	/// Returns the payload if this is a custom action carrying a `T`.
+	pub fn get_custom<T: CustomPayload>(&self) -> Option<&T> {
+		match self {
+			Action::CustomAction(payload) => (**payload).as_any().downcast_ref::<T>(),
+			_ => None,
+		}
+	}
+}
+
+/// The layers the renderer paints, from the bottom up.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Layer {
+	Background,
+	World,
+	Ui,
+	/// Where the images of menus go, on top of everything else.
+	Menu,
+}
+
+impl Layer {
+	/// Whether images on this layer are placed in world coordinates, which the camera turns into
+	/// screen coordinates. True for the background and the world.
+	pub fn is_in_world_space(&self) -> bool {
+		match *self {
+			Layer::Background | Layer::World => true,
+			Layer::Ui | Layer::Menu => false,
+		}
+	}
+}
+
+/// Where an image goes in the order of painting: layer by layer, and inside a layer from low to
+/// high `z`. Images with the same layer and `z` are painted in the order they arrived.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Depth {
+	pub layer: Layer,
+	pub z: i32,
+}
+
+impl Depth {
+	pub fn new(layer: Layer, z: i32) -> Self {
+		Self {
+			layer,
+			z,
+		}
+	}
+}
+
+/// An action on its way to a single store. The store receives the wrapped action no matter what
+/// it subscribed to, and can find out who sent it through its context.
+pub struct Envelope {
+	pub to: StoreId,
+	/// The sending store, `None` if the action was sent on the dispatcher.
+	pub from: Option<StoreId>,
+	/// Set if the sender waits for an answer, see `Context::reply`.
+	pub request: Option<RequestId>,
+	/// Set if this is the answer to a request.
+	pub reply_to: Option<RequestId>,
+	pub action: Box<Action>,
+}
+
+/// Lets `CustomPayload` trait objects be downcast. Implemented for every type, there's no need to
+/// implement it by hand.
+pub trait AsAny {
+	fn as_any(&self) -> &Any;
+}
+
+impl<T: Any> AsAny for T {
+	fn as_any(&self) -> &Any {
+		self
+	}
+}
+
+/// The payload of a custom action. Games implement this for their own message types and send them
+/// with `Action::custom`, stores get them back with `Action::get_custom`.
+///
+/// Every payload type is its own kind of action, so stores subscribe to `ActionKind::custom::<T>()`.
+pub trait CustomPayload: AsAny + Send + Sync + 'static {
+	/// A single line of text describing the payload, used when recording the action stream. Two
+	/// payloads that describe the same should be the same. Defaults to the name of the type.
+	fn describe(&self) -> String {
+		String::from(::std::any::type_name::<Self>())
+	}
+}
+
+/// The type of an action without its payload. Used by stores to tell the dispatcher which actions
+/// they want to receive.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ActionKind {
+	AddImgToCanvasAction,
+	DrawAction,
+	UpdateAction,
+	SendFrameAction,
+	EndFrameAction,
+	StartAction,
+	MenuAction,
+	CameraAction,
+	QuitAction,
+	EmptyAction,
+	KeyboardAction,
+	RemoveStoreAction,
+	CancelTimerAction,
+	DirectedAction,
+	GetCanvasSizeAction,
+	CanvasSizeAction,
+	VsyncAction,
+	TimeScaleAction,
+	StepFrameAction,
+	SaveAction,
+	LoadAction,
+	AssetReloadAction,
+	StoreErrorAction,
+	CustomAction(TypeId),
+}
+
+impl ActionKind {
+	/// The kind of custom actions carrying a `T`.
+	pub fn custom<T: CustomPayload>() -> Self {
+		ActionKind::CustomAction(TypeId::of::<T>())
+	}
 }
 
 /// Similar to the action Enum. Only meant for use in menu states.
 pub enum MenuSubAction {
+	/// Sent when Escape is pressed. The dispatcher turns it into `PushMenuAction(0)` if no menu is
+	/// open and into `PopMenuAction` otherwise.
 	ChangeMenuStateAction,
+	/// Opens a menu showing the scene with the given index, on top of the game or of the menu
+	/// that is open.
+	PushMenuAction(usize),
+	/// Closes the topmost menu. The game goes on once the last one is closed.
+	PopMenuAction,
 	WaitForInputAction,
 	DrawAction,
-	AddImgToCanvasAction(u32, u32, Img),
+	/// Like `Action::AddImgToCanvasAction`, on the menu layer.
+	AddImgToCanvasAction(u32, u32, ImgHandle),
 	ClickAction(i32, i32, MouseButton, u32),
 	QuitAction,
 }
+
+/// Moves the renderer's `Camera`. Positions and sizes are in world pixels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraSubAction {
+	/// Shows the given world position in the top left corner.
+	MoveToAction(i32, i32),
+	/// Moves the view by the given distance.
+	ScrollAction(i32, i32),
+	/// Sets the zoom level, see `Camera::set_zoom`.
+	ZoomAction(u32),
+	/// Centers the view on a rectangle: x, y, width and height. Send it every frame with the
+	/// position and size of a sprite to follow the sprite.
+	FollowAction(i32, i32, u32, u32),
+	/// Shakes the view by up to the given number of game pixels, for the given number of seconds.
+	ShakeAction(u32, f64),
+}
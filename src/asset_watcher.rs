@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::action::{Action, ActionKind};
+use super::store::{Context, Store, StoreResult};
+
+/// Watches a directory during development and sends an `AssetReloadAction` whenever a file in it
+/// is changed or added, so stores can swap in the new version without a restart.
+///
+/// Works by polling modification times (and sizes) once every `interval` of real time, at the end
+/// of a frame. Only the directory itself is watched, not its subdirectories. Reloads depend on
+/// what happens on disk, so don't record replays with a watcher entered.
+pub struct AssetWatcher {
+	dir: PathBuf,
+	extension: Option<String>,
+	interval: Duration,
+	last_poll: Instant,
+	known: HashMap<PathBuf, (SystemTime, u64)>,
+}
+
+impl AssetWatcher {
+	/// Starts watching a directory. Files that are there already don't count as changed.
+	pub fn new<P: AsRef<Path>>(dir: P, interval: Duration) -> Self {
+		let mut watcher = Self {
+			dir: dir.as_ref().to_path_buf(),
+			extension: None,
+			interval,
+			last_poll: Instant::now(),
+			known: HashMap::new(),
+		};
+		match watcher.poll() {
+			Ok(_) => {}
+			Err(e) => frame_error!("Could not watch {}: {}", watcher.dir.display(), e),
+		}
+		return watcher;
+	}
+
+	/// Only watches files with the given extension, e.g. `"u4i"`.
+	pub fn with_extension(mut self, extension: &str) -> Self {
+		self.extension = Some(String::from(extension));
+		self.known.retain(|path, _| path.extension().map_or(false, |ext| ext == extension));
+		self
+	}
+
+	/// Looks at the directory right away and returns the files that were changed or added since
+	/// the last look, sorted by path.
+	pub fn poll(&mut self) -> io::Result<Vec<PathBuf>> {
+		self.last_poll = Instant::now();
+		let mut changed = vec![];
+		let mut seen = vec![];
+
+		for entry in fs::read_dir(&self.dir)? {
+			let entry = entry?;
+			let metadata = entry.metadata()?;
+			if !metadata.is_file() {
+				continue;
+			}
+			let path = entry.path();
+			match self.extension {
+				Some(ref extension) => {
+					if !path.extension().map_or(false, |ext| ext == extension.as_str()) {
+						continue;
+					}
+				}
+				None => {}
+			}
+
+			let stamp = (metadata.modified()?, metadata.len());
+			if self.known.get(&path) != Some(&stamp) {
+				changed.push(path.clone());
+				self.known.insert(path.clone(), stamp);
+			}
+			seen.push(path);
+		}
+
+		self.known.retain(|path, _| seen.contains(path));
+		changed.sort();
+		Ok(changed)
+	}
+}
+
+impl Store for AssetWatcher {
+	/// Fails if the directory can't be read, or if a changed file can't be. The other changed
+	/// files are still sent in that case.
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::EndFrameAction => {
+				if self.last_poll.elapsed() < self.interval {
+					return Ok(());
+				}
+				let changed = match self.poll() {
+					Ok(changed) => changed,
+					Err(e) => return Err(format!("Could not watch {}: {}", self.dir.display(), e)),
+				};
+				let mut result = Ok(());
+				for path in changed {
+					match fs::read(&path) {
+						Ok(bytes) => ctx.send(Action::AssetReloadAction(path, bytes)),
+						Err(e) => {
+							if result.is_ok() {
+								result = Err(format!("Could not reload {}: {}", path.display(), e));
+							}
+						}
+					}
+				}
+				return result;
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::EndFrameAction])
+	}
+}
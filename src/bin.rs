@@ -9,22 +9,23 @@ mod light_path;
 
 fn main() {
 
-	let mut ship = space_ship::Spaceship::new();
-	let mut renderer = frame::renderer::Renderer::new(512, 512, None);
+	let ship = space_ship::Spaceship::new();
+	let renderer = frame::renderer::Renderer::new(512, 512, None);
 
-	let mut menu = main_menu::MainMenu::new();
+	let menu = main_menu::MainMenu::new();
 	let mut scene_manager = SceneManager::new();
 
-	scene_manager.add_scenes(vec![&mut menu]);
+	scene_manager.add_scenes(vec![Box::new(menu)]);
 
 	let mut disp = frame::dispatcher::Dispatcher::new(1.0 / 60.0);
+	disp.set_target_fps(Some(60.0));
 
 	frame::game_loop(
 		&mut disp,
 		vec![
-			&mut ship,
-			&mut renderer,
-			&mut scene_manager,
+			Box::new(ship),
+			Box::new(renderer),
+			Box::new(scene_manager),
 		],
 	);
 }
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use super::action::MenuSubAction;
 use super::sprite::Sprite;
 
@@ -21,15 +23,23 @@ impl Button {
 		self.click_fn = fun;
 	}
 
+	/// Takes the new frames of a reloaded asset if it's the source of the button's sprite, see
+	/// `Sprite::reload_asset`.
+	pub fn reload_asset(&mut self, path: &Path, buf: &[u8]) -> bool {
+		self.sprite.reload_asset(path, buf)
+	}
+
 	/// Calls the function closure when a click is detected upon the button.
 	pub fn check_click(&self, x: u32, y: u32, px_size: u32) -> Option<MenuSubAction> {
 
 		let sprite_x = &self.sprite.get_pos().0 * px_size;
 		let sprite_y = &self.sprite.get_pos().1 * px_size;
 
-		println!("cx:{}, cy:{}", sprite_x, sprite_y);
-		println!("cx:{}, cy:{}", x, y);
-		println!("cx:{}, cy:{}", sprite_x + self.sprite.get_width() * px_size, sprite_y + self.sprite.get_height() * px_size);
+		frame_trace!(
+			"Click at {}, {} on a button from {}, {} to {}, {}",
+			x, y, sprite_x, sprite_y,
+			sprite_x + self.sprite.get_width() * px_size, sprite_y + self.sprite.get_height() * px_size
+		);
 
 		if sprite_x < x && x < sprite_x + self.sprite.get_width() * px_size {
 			if sprite_y < y && y < sprite_y + self.sprite.get_height() * px_size {
@@ -0,0 +1,143 @@
+use rand::Rng;
+
+use super::rng::GameRng;
+
+/// Turns world coordinates into screen coordinates for the images on the background and world
+/// layers. Images on the UI and menu layers stay in screen space.
+///
+/// Both are counted in game pixels: the world position of the camera is the world pixel shown in
+/// the top left corner, and a world pixel covers `zoom` by `zoom` game pixels on the screen.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Camera {
+	x: i32,
+	y: i32,
+	zoom: u32,
+	shake: Option<Shake>,
+	shake_offset: (i32, i32),
+	// Only for shakes, so cosmetics don't change what the game's own generator draws.
+	rng: GameRng,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Shake {
+	amplitude: u32,
+	duration: f64,
+	left: f64,
+}
+
+impl Camera {
+	/// A camera at the world origin, without zoom.
+	pub fn new() -> Self {
+		Self {
+			x: 0,
+			y: 0,
+			zoom: 1,
+			shake: None,
+			shake_offset: (0, 0),
+			rng: GameRng::new(0),
+		}
+	}
+
+	/// Seeds the generator shakes are drawn from, e.g. with the seed of the game. It's separate
+	/// from the dispatcher's, so a game shakes the same in replays and draws the same numbers
+	/// with or without a renderer.
+	pub fn set_shake_seed(&mut self, seed: u64) {
+		self.rng = GameRng::new(seed);
+	}
+
+	/// Returns the world position shown in the top left corner.
+	pub fn get_pos(&self) -> (i32, i32) {
+		(self.x, self.y)
+	}
+
+	pub fn set_pos(&mut self, pos: (i32, i32)) {
+		self.x = pos.0;
+		self.y = pos.1;
+	}
+
+	/// Moves the view by the given number of world pixels.
+	pub fn scroll(&mut self, dx: i32, dy: i32) {
+		self.x += dx;
+		self.y += dy;
+	}
+
+	pub fn get_zoom(&self) -> u32 {
+		self.zoom
+	}
+
+	/// Sets how many game pixels on the screen a world pixel covers in each direction. Zooming in
+	/// keeps the top left corner where it is. Values below 1 count as 1.
+	pub fn set_zoom(&mut self, zoom: u32) {
+		self.zoom = zoom.max(1);
+	}
+
+	/// Moves the view so the given rectangle of the world (e.g. a sprite) is in the middle of a
+	/// view of `view_width` by `view_height` game pixels.
+	pub fn center_on(&mut self, x: i32, y: i32, width: u32, height: u32, view_width: u32, view_height: u32) {
+		let zoom = self.zoom as i32;
+		self.x = x + width as i32 / 2 - view_width as i32 / zoom / 2;
+		self.y = y + height as i32 / 2 - view_height as i32 / zoom / 2;
+	}
+
+	/// Shakes the view by up to `amplitude` game pixels in every direction, calming down over
+	/// `duration` seconds. A new shake replaces the one going on.
+	pub fn shake(&mut self, amplitude: u32, duration: f64) {
+		self.shake = if amplitude > 0 && duration > 0.0 {
+			Some(Shake {
+				amplitude,
+				duration,
+				left: duration,
+			})
+		} else {
+			None
+		};
+		self.shake_offset = (0, 0);
+	}
+
+	/// Tells if the view is shaking.
+	pub fn is_shaking(&self) -> bool {
+		self.shake.is_some()
+	}
+
+	/// Moves an ongoing shake on by `dt` seconds and picks the next offset. The renderer does this
+	/// once every frame with the real frame time, so a shake lasts as long when the game is slowed
+	/// down or stepped.
+	pub fn update(&mut self, dt: f64) {
+		let strength = match self.shake {
+			Some(ref mut shake) => {
+				shake.left -= dt;
+				shake.amplitude as f64 * shake.left / shake.duration
+			}
+			None => return,
+		};
+		if strength <= 0.0 {
+			self.shake = None;
+			self.shake_offset = (0, 0);
+			return;
+		}
+		let range = strength.round() as i32;
+		self.shake_offset = (self.rng.gen_range(-range, range + 1), self.rng.gen_range(-range, range + 1));
+	}
+
+	/// Returns where a world position ends up on the screen, in game pixels.
+	pub fn world_to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+		let zoom = self.zoom as i32;
+		((x - self.x) * zoom + self.shake_offset.0, (y - self.y) * zoom + self.shake_offset.1)
+	}
+
+	/// Returns the world position shown at a point of the screen given in game pixels, e.g. where
+	/// the player clicked. The shake is left out.
+	pub fn screen_to_world(&self, x: i32, y: i32) -> (i32, i32) {
+		let zoom = self.zoom as i32;
+		(self.x + div_floor(x, zoom), self.y + div_floor(y, zoom))
+	}
+}
+
+fn div_floor(a: i32, b: i32) -> i32 {
+	let quotient = a / b;
+	if a % b != 0 && (a < 0) != (b < 0) {
+		quotient - 1
+	} else {
+		quotient
+	}
+}
@@ -0,0 +1,214 @@
+use std::time::Instant;
+
+/// How the dispatcher's clock advances.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ClockMode {
+	/// `dt` is the real time the last frame took.
+	Variable,
+	/// Updates happen in fixed steps of the given length (in seconds). Real time is collected in
+	/// an accumulator and every frame runs as many steps as fit into it, so the simulation runs at
+	/// the same rate no matter how often frames are drawn.
+	Fixed(f64),
+}
+
+/// The time handed to the stores. It's the same for every store during a frame (or during a fixed
+/// update step).
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GameTime {
+	/// Seconds since the last frame, or the length of a step in fixed mode.
+	pub dt: f64,
+	/// Number of the current frame. Counts up by one on every `EndFrameAction`.
+	pub frame: u64,
+	/// Game time in seconds. Doesn't advance while in a menu.
+	pub total: f64,
+}
+
+/// The dispatcher's clock. Turns real time into `GameTime`.
+pub struct GameClock {
+	mode: ClockMode,
+	time: GameTime,
+	last_frame_start: Instant,
+	paused_at: Option<Instant>,
+	accumulator: f64,
+	max_steps: u32,
+	time_scale: f64,
+	pending_steps: u32,
+}
+
+impl GameClock {
+	/// Creates a clock in the given mode. A fixed step that isn't a positive number falls back to
+	/// variable mode, see `set_mode`.
+	pub fn new(mode: ClockMode) -> Self {
+		let mut clock = Self {
+			mode: ClockMode::Variable,
+			time: GameTime {
+				dt: 0.0,
+				frame: 0,
+				total: 0.0,
+			},
+			last_frame_start: Instant::now(),
+			paused_at: None,
+			accumulator: 0.0,
+			max_steps: 5,
+			time_scale: 1.0,
+			pending_steps: 0,
+		};
+		match clock.set_mode(mode) {
+			Ok(_) => {},
+			Err(e) => frame_warn!("{}", e),
+		}
+		return clock;
+	}
+
+	pub fn get_mode(&self) -> ClockMode {
+		self.mode
+	}
+
+	/// Changes the mode. Whatever was left in the accumulator is dropped. Fails and keeps the
+	/// current mode if a fixed step isn't a finite number above 0, which would never be used up.
+	pub fn set_mode(&mut self, mode: ClockMode) -> Result<(), String> {
+		match mode {
+			ClockMode::Fixed(step) if !(step.is_finite() && step > 0.0) => {
+				return Err(format!("Invalid fixed step length: {}", step));
+			}
+			_ => {}
+		}
+		self.mode = mode;
+		self.accumulator = 0.0;
+		match mode {
+			ClockMode::Fixed(step) => self.time.dt = step,
+			ClockMode::Variable => {}
+		}
+		Ok(())
+	}
+
+	/// Sets how many fixed steps may run in one frame at most. If a frame took longer than that,
+	/// the rest of the time is dropped instead of piling up.
+	pub fn set_max_steps(&mut self, max_steps: u32) {
+		self.max_steps = max_steps;
+	}
+
+	/// Scales the time that passes in the game: 0.5 is slow motion, 2 runs twice as fast and 0
+	/// freezes the game while frames keep being drawn. In variable mode `dt` is scaled, in fixed
+	/// mode the steps stay the same length but come more or less often. Negative values count as 0.
+	pub fn set_time_scale(&mut self, time_scale: f64) {
+		self.time_scale = if time_scale > 0.0 { time_scale } else { 0.0 };
+	}
+
+	pub fn get_time_scale(&self) -> f64 {
+		self.time_scale
+	}
+
+	/// Lets exactly one frame of game time pass while the time scale is 0: the next frame gets
+	/// the real time it took in variable mode, or a single step in fixed mode. Calling it several
+	/// times steps several frames, one per frame. Does nothing while time isn't frozen.
+	pub fn step_frame(&mut self) {
+		if self.time_scale == 0.0 {
+			self.pending_steps += 1;
+		}
+	}
+
+	/// Returns the current time.
+	pub fn get_time(&self) -> GameTime {
+		self.time
+	}
+
+	/// Returns how far the accumulator has filled up towards the next fixed step, from 0 to 1.
+	/// Useful for interpolating positions when drawing. Always 0 in variable mode.
+	pub fn get_alpha(&self) -> f64 {
+		match self.mode {
+			ClockMode::Variable => 0.0,
+			ClockMode::Fixed(step) => self.accumulator / step,
+		}
+	}
+
+	/// Starts a new frame. Called by the dispatcher on every `EndFrameAction`. Returns the real time
+	/// that passed since the last frame.
+	pub fn start_frame(&mut self) -> f64 {
+		let now = Instant::now();
+		let elapsed = match self.paused_at {
+			Some(_) => 0.0,
+			None => now.duration_since(self.last_frame_start).as_secs_f64(),
+		};
+		self.start_frame_with(elapsed);
+		return elapsed;
+	}
+
+	/// Starts a new frame as if `elapsed` seconds of real time had passed since the last one, e.g.
+	/// when replaying a recording.
+	pub fn start_frame_with(&mut self, elapsed: f64) {
+		self.last_frame_start = Instant::now();
+		self.time.frame += 1;
+
+		let stepping = self.time_scale == 0.0 && self.pending_steps > 0;
+		if stepping {
+			self.pending_steps -= 1;
+		}
+		if self.time_scale != 0.0 {
+			self.pending_steps = 0;
+		}
+
+		match self.mode {
+			ClockMode::Variable => {
+				let scaled = if stepping { elapsed } else { elapsed * self.time_scale };
+				self.time.dt = scaled;
+				self.time.total += scaled;
+			}
+			ClockMode::Fixed(step) => {
+				self.accumulator += if stepping { step } else { elapsed * self.time_scale };
+				if self.accumulator > step * self.max_steps as f64 {
+					self.accumulator = step * self.max_steps as f64;
+				}
+			}
+		}
+	}
+
+	/// Returns how many update steps are due in this frame and takes them out of the accumulator.
+	/// In variable mode there's always exactly one, in fixed mode never more than the maximum set
+	/// with `set_max_steps`.
+	pub fn take_update_steps(&mut self) -> u32 {
+		match self.mode {
+			ClockMode::Variable => 1,
+			ClockMode::Fixed(step) => {
+				let mut steps = 0;
+				while self.accumulator >= step && steps < self.max_steps {
+					self.accumulator -= step;
+					steps += 1;
+				}
+				steps
+			}
+		}
+	}
+
+	/// Advances the game time by one fixed step. Called before every step taken with
+	/// `take_update_steps`. Does nothing in variable mode, where the time moves per frame.
+	pub fn advance_step(&mut self) {
+		match self.mode {
+			ClockMode::Variable => {}
+			ClockMode::Fixed(step) => self.time.total += step,
+		}
+	}
+
+	/// Stops the clock, e.g. when a menu is opened.
+	pub fn pause(&mut self) {
+		match self.paused_at {
+			Some(_) => {}
+			None => self.paused_at = Some(Instant::now()),
+		}
+	}
+
+	/// Tells if the clock is stopped.
+	pub fn is_paused(&self) -> bool {
+		self.paused_at.is_some()
+	}
+
+	/// Starts the clock again. The time spent paused doesn't count towards the next frame.
+	pub fn resume(&mut self) {
+		match self.paused_at.take() {
+			Some(paused_at) => {
+				self.last_frame_start += paused_at.elapsed();
+			}
+			None => {}
+		}
+	}
+}
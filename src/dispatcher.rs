@@ -1,14 +1,198 @@
+use std::any::Any;
+use std::collections::HashMap;
 use std::collections::VecDeque;
-use std::time::Instant;
+use std::io;
+use std::mem;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How much earlier than needed the frame limiter wakes up, since sleeping is never exact. The
+/// rest is waited out by yielding.
+const PACING_MARGIN: f64 = 0.001;
 
 use action::Action::MenuAction;
 
 use super::action::*;
+use super::clock::*;
+use super::middleware::Middleware;
+use super::profiler::Profiler;
+use super::recorder::*;
+use super::rng::GameRng;
+use super::save::SaveFile;
 use super::store::*;
+use super::timer::*;
+
+/// Stores that are `Send` can receive actions on worker threads.
+enum StoreSlot {
+	Local(Box<Store>),
+	Shared(Box<Store + Send>),
+	/// A shared store while a worker has it. It's back before `walk_parallel` returns.
+	Lent,
+}
+
+impl StoreSlot {
+	fn get(&self) -> &Store {
+		match self {
+			StoreSlot::Local(store) => &**store,
+			StoreSlot::Shared(store) => &**store,
+			StoreSlot::Lent => unreachable!("The store is with a worker"),
+		}
+	}
+
+	fn get_mut(&mut self) -> &mut Store {
+		match self {
+			StoreSlot::Local(store) => &mut **store,
+			StoreSlot::Shared(store) => &mut **store,
+			StoreSlot::Lent => unreachable!("The store is with a worker"),
+		}
+	}
+
+	fn into_store(self) -> Box<Store> {
+		match self {
+			StoreSlot::Local(store) => store,
+			StoreSlot::Shared(store) => store,
+			StoreSlot::Lent => unreachable!("The store is with a worker"),
+		}
+	}
+}
+
+/// A shared store handed to a worker, with the action to give it and what its context starts
+/// with.
+struct Job {
+	index: usize,
+	id: StoreId,
+	store: Box<Store + Send>,
+	action: Arc<Action>,
+	time: GameTime,
+	frame_time: f64,
+	next_store_id: u64,
+	next_timer_seq: u64,
+	next_request_seq: u64,
+	rng: GameRng,
+}
+
+/// What a worker hands back: the store and what it sent. `panic` holds what the store panicked
+/// with, if it did.
+struct JobResult {
+	index: usize,
+	id: StoreId,
+	store: Box<Store + Send>,
+	sent: Sent,
+	result: StoreResult,
+	panic: Option<Box<Any + Send>>,
+	started: Instant,
+	duration: Duration,
+}
+
+/// The threads `walk_parallel` hands shared stores to. They are started once and wait for jobs
+/// until the pool is dropped.
+struct WorkerPool {
+	jobs: Vec<Sender<Job>>,
+	results: Receiver<JobResult>,
+}
+
+impl WorkerPool {
+	fn new(workers: usize) -> Self {
+		let (results_sender, results) = channel();
+		let mut jobs = vec![];
+		for _ in 0..workers {
+			let (jobs_sender, jobs_receiver) = channel::<Job>();
+			let results_sender = results_sender.clone();
+			thread::spawn(move || {
+				for job in jobs_receiver.iter() {
+					if results_sender.send(run_job(job)).is_err() {
+						return;
+					}
+				}
+			});
+			jobs.push(jobs_sender);
+		}
+		Self {
+			jobs,
+			results,
+		}
+	}
+}
+
+/// Used by the worker threads.
+/// Calls the store of a job. The action is let go of before the result is handed back, so the
+/// dispatcher can have it back once all results are in.
+fn run_job(job: Job) -> JobResult {
+	let Job { index, id, mut store, action, time, frame_time, next_store_id, next_timer_seq, next_request_seq, rng } = job;
+	let mut ctx = Context::new(time, id, next_store_id);
+	ctx.next_timer_seq = next_timer_seq;
+	ctx.next_request_seq = next_request_seq;
+	ctx.rng = rng;
+	ctx.frame_time = frame_time;
+	let started = Instant::now();
+	let outcome = panic::catch_unwind(AssertUnwindSafe(|| store.receive_action(&action, &mut ctx)));
+	let duration = started.elapsed();
+	drop(action);
+
+	let (added_stores, sent) = ctx.split();
+	let (result, panic) = match outcome {
+		// The new stores may not be `Send`, so they can't come back from here.
+		Ok(Ok(_)) if !added_stores.is_empty() => {
+			(Err(String::from("Stores can't add other stores while receiving actions in parallel")), None)
+		}
+		Ok(result) => (result, None),
+		Err(payload) => (Ok(()), Some(payload)),
+	};
+	JobResult {
+		index,
+		id,
+		store,
+		sent,
+		result,
+		panic,
+		started,
+		duration,
+	}
+}
+
+/// An entered store and what the dispatcher keeps about it.
+struct StoreEntry {
+	id: StoreId,
+	store: StoreSlot,
+	subscriptions: Option<Vec<ActionKind>>,
+	// None means the store wants every action.
+	input_source: bool,
+	next_timer_seq: u64,
+	next_request_seq: u64,
+	removed: bool,
+	// Removed stores are skipped and only thrown out at the start of the next dispatch, so the
+	// indices stay valid while the stores are walked.
+}
+
+/// A menu opened on top of the game or on top of another menu.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MenuLayer {
+	/// The index of the scene the menu shows.
+	pub scene: usize,
+	/// How far the frame (or the menu) below had got when the menu was opened.
+	pub paused_at: Duration,
+}
+
+/// What the dispatcher does when a store returns an error from `receive_action`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ErrorPolicy {
+	/// Logs the error and sends a `StoreErrorAction`. The store keeps getting actions.
+	LogAndContinue,
+	/// Like `LogAndContinue`, but also removes the store, as a `RemoveStoreAction` would.
+	DisableStore,
+	/// Logs the error and stops the game loop. Stores still waiting for the action don't get it.
+	StopLoop,
+}
 
 pub enum MenuState {
 	NotInMenu,
-	InMenu(std::time::Duration),
+	/// The open menus, the topmost one last. Never empty.
+	InMenu(Vec<MenuLayer>),
 }
 
 ///A Dispatcher in the Flux pattern sense. All traffic goes through here (in the form of Actions).
@@ -16,40 +200,326 @@ pub enum MenuState {
 /// This implementation uses a primary and a secondary FiFo stack. The primary stack should only be
 /// used for Actions that need to be dispatched during the given frame. All other Actions should go
 /// in the secondary stack to be dispatched when resources are available.
-/// All Actions sent by Menus should also be sent to the second stack.
-pub struct Dispatcher<'a> {
+/// Actions sent by Menus go on the primary stack, as it's the only one read while a menu is open.
+///
+/// While a menu is open, only menu actions (and quitting) are dispatched. Other actions sent to
+/// the primary stack are parked and dispatched in their original order once the last menu is
+/// closed, before anything sent after that. The secondary stack waits as well. Menu actions that
+/// turn up when no menu is open anymore are dropped with a warning, except for the ones that open
+/// a menu or quit. Closing a menu when none is open does nothing.
+pub struct Dispatcher {
 	primary_action_queue: VecDeque<Action>,
 	secondary_action_queue: VecDeque<Action>,
-	pub store_refs: Option<Vec<Option<&'a mut Store<'a>>>>,
-	//the double use of Option is necessary for memory safety. Long explanation in dispatch method.
+	parked_actions: VecDeque<Action>,
+	// Gameplay actions taken from the primary queue while a menu was open.
+	store_entries: Vec<StoreEntry>,
+	next_store_id: u64,
+	routes: HashMap<ActionKind, Vec<usize>>,
+	// Routing table: the indices of all stores that want an action kind, in store order.
+	store_generation: u64,
+	// Counts up whenever stores are added or removed, which makes the routing table stale.
+	input_polls: u64,
+	// How often an input source was called. Recorded input is matched up by this number.
+	middleware_inputs: u64,
+	// How much player input went through the middleware. Dropped input is recorded by this number.
+	recorder: Option<Recorder>,
+	replay: Option<Replay>,
+	profiler: Option<Profiler>,
+	middleware: Vec<Box<Middleware>>,
+	timers: Timers,
+	next_timer_id: u64,
+	parallel_kinds: Vec<ActionKind>,
+	workers: Option<WorkerPool>,
 	pub menu_state: MenuState,
 	pub use_secondary: bool,
 	max_stack_time: f64,
 	current_stack_start_time: Instant,
-	dt: f64,
+	target_frame_time: Option<f64>,
+	simulated_frame_time: Option<f64>,
+	frame_time: f64,
+	vsync: bool,
+	clock: GameClock,
+	error_policy: ErrorPolicy,
+	stopped: bool,
+	// Set when a store failed under `ErrorPolicy::StopLoop`.
+	seed: u64,
+	rng: GameRng,
 }
 
-impl<'a> Dispatcher<'a> {
-	///Creates a new Dispatcher object.
+impl Dispatcher {
+	///Creates a new Dispatcher object. The random number generator gets a random seed.
 	pub fn new(max_stack_time: f64) -> Self {
+		Self::new_seeded(max_stack_time, rand::random())
+	}
+
+	/// Creates a dispatcher whose random number generator starts with the given seed, so the
+	/// stores draw the same numbers on every run.
+	pub fn new_seeded(max_stack_time: f64, seed: u64) -> Self {
 		return Self {
 			primary_action_queue: VecDeque::from(vec!(Action::StartAction, Action::EndFrameAction)),
 			secondary_action_queue: VecDeque::new(),
-			store_refs: None,
+			parked_actions: VecDeque::new(),
+			store_entries: vec![],
+			next_store_id: 0,
+			routes: HashMap::new(),
+			store_generation: 0,
+			input_polls: 0,
+			middleware_inputs: 0,
+			recorder: None,
+			replay: None,
+			profiler: None,
+			middleware: vec![],
+			timers: Timers::new(),
+			next_timer_id: 0,
+			parallel_kinds: vec![],
+			workers: None,
 			menu_state: MenuState::NotInMenu,
 			use_secondary: false,
 			max_stack_time,
 			current_stack_start_time: Instant::now(),
-			dt: 0.0,
+			target_frame_time: None,
+			simulated_frame_time: None,
+			frame_time: 0.0,
+			vsync: false,
+			clock: GameClock::new(ClockMode::Variable),
+			error_policy: ErrorPolicy::LogAndContinue,
+			stopped: false,
+			seed,
+			rng: GameRng::new(seed),
 		}
 	}
 
+	/// Limits the frame rate: if a frame is done early, the dispatcher sleeps until it has taken
+	/// `1 / fps` seconds. `None` (the default) lets frames run as fast as they can. Does nothing
+	/// while the renderer presents with vsync, see `set_vsync`.
+	pub fn set_target_fps(&mut self, fps: Option<f64>) {
+		self.target_frame_time = match fps {
+			Some(fps) if fps > 0.0 => Some(1.0 / fps),
+			_ => None,
+		};
+	}
+
+	pub fn get_target_fps(&self) -> Option<f64> {
+		match self.target_frame_time {
+			Some(frame_time) => Some(1.0 / frame_time),
+			None => None,
+		}
+	}
+
+	/// Makes every frame count as exactly `frame_time` seconds, no matter how long it really took,
+	/// e.g. to simulate a game faster than real time or to get the same times in every test run.
+	/// Frames then never wait for the frame limiter and are never cut short by the maximum stack
+	/// time: each one lasts until both stacks are empty. `None` (the default) goes back to real
+	/// time.
+	pub fn set_simulated_frame_time(&mut self, frame_time: Option<f64>) {
+		self.simulated_frame_time = frame_time;
+	}
+
+	pub fn get_simulated_frame_time(&self) -> Option<f64> {
+		self.simulated_frame_time
+	}
+
+	/// Tells the dispatcher whether presenting waits for the vertical sync. The renderer does
+	/// this on its own with a `VsyncAction` when the game starts.
+	pub fn set_vsync(&mut self, vsync: bool) {
+		self.vsync = vsync;
+	}
+
+	pub fn is_vsync(&self) -> bool {
+		self.vsync
+	}
+
+	/// Switches the clock between variable and fixed time steps. Fails on a fixed step that isn't
+	/// a finite number above 0.
+	pub fn set_clock_mode(&mut self, mode: ClockMode) -> Result<(), String> {
+		self.clock.set_mode(mode)
+	}
+
+	/// Returns the clock, e.g. to read the current frame number or the interpolation alpha.
+	pub fn get_clock(&mut self) -> &mut GameClock {
+		&mut self.clock
+	}
+
+	/// Decides what happens when a store fails to handle an action. The default is
+	/// `ErrorPolicy::LogAndContinue`.
+	pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+		self.error_policy = policy;
+	}
+
+	pub fn get_error_policy(&self) -> ErrorPolicy {
+		self.error_policy
+	}
+
+	/// Returns the seed the dispatcher was created with.
+	pub fn get_seed(&self) -> u64 {
+		self.seed
+	}
+
+	/// Returns the random number generator the stores draw from, e.g. to generate a map before
+	/// the game starts.
+	pub fn get_rng(&mut self) -> &mut GameRng {
+		&mut self.rng
+	}
+
+	/// Starts writing every dispatched action to a file, together with the frame times, the
+	/// player input sent by input sources and the state of the random number generator. The file
+	/// can be played back with `replay_from`.
+	pub fn record_to<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+		let mut recorder = Recorder::create(path)?;
+		recorder.record_rng(self.rng.get_state());
+		self.recorder = Some(recorder);
+		Ok(())
+	}
+
+	/// Stops recording and writes what's left to the file.
+	pub fn stop_recording(&mut self) {
+		match self.recorder.take() {
+			Some(mut recorder) => recorder.flush(),
+			None => {}
+		}
+	}
+
+	/// Plays back a recording: the player input sent by input sources is thrown away and replaced
+	/// by the recorded input, and frames take exactly as long as they did when recording. In
+	/// menus, input sources aren't asked to wait for input but get the recorded input right away.
+	/// Every dispatched action is compared with the recorded one, see `get_replay_divergence`. The
+	/// dispatcher stops once all recorded actions have been dispatched again.
+	///
+	/// The random number generator is put back to the state it had when recording started. Has
+	/// to be called before the first dispatch.
+	pub fn replay_from<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+		let replay = Replay::open(path)?;
+		match replay.get_rng_state() {
+			Some(state) => self.rng.set_state(state),
+			None => {}
+		}
+		self.replay = Some(replay);
+		Ok(())
+	}
+
+	/// Returns the first point where the replay dispatched something else than the recording.
+	/// `None` if it didn't (yet) or if there is no replay.
+	pub fn get_replay_divergence(&self) -> Option<&Divergence> {
+		match self.replay {
+			Some(ref replay) => replay.get_divergence(),
+			None => None,
+		}
+	}
+
+	/// Starts measuring how long every store takes to handle its actions, and how much of the
+	/// secondary queue gets worked off each frame.
+	pub fn enable_profiling(&mut self) {
+		match self.profiler {
+			Some(_) => {}
+			None => self.profiler = Some(Profiler::new()),
+		}
+	}
+
+	/// Stops profiling and hands back everything that was measured.
+	pub fn disable_profiling(&mut self) -> Option<Profiler> {
+		self.profiler.take()
+	}
+
+	/// Returns the profiler while profiling is enabled, e.g. to show the last frame in a debug
+	/// overlay.
+	pub fn get_profiler(&self) -> Option<&Profiler> {
+		self.profiler.as_ref()
+	}
+
+	/// Returns the profiler while profiling is enabled, e.g. to change how many frames it keeps.
+	pub fn get_profiler_mut(&mut self) -> Option<&mut Profiler> {
+		self.profiler.as_mut()
+	}
+
+	/// Adds a middleware to the end of the chain. Every action taken from the stacks goes through
+	/// the chain before it's dispatched.
+	pub fn add_middleware(&mut self, middleware: Box<Middleware>) {
+		self.middleware.push(middleware);
+	}
+
+	/// Sends an action to the primary queue once `delay` seconds of game time have passed. Game
+	/// time stands still in menus, so do timers.
+	pub fn send_after(&mut self, delay: f64, action: Action) -> TimerId {
+		let id = self.take_timer_id();
+		let due = Due::Time(self.clock.get_time().total + delay);
+		self.timers.add(Timer::once(id, due, action));
+		return id;
+	}
+
+	/// Sends an action to the primary queue at the start of the given frame.
+	pub fn send_at_frame(&mut self, frame: u64, action: Action) -> TimerId {
+		let id = self.take_timer_id();
+		self.timers.add(Timer::once(id, Due::Frame(frame), action));
+		return id;
+	}
+
+	/// Sends the action built by `make` every `interval` seconds of game time, starting in
+	/// `interval` seconds, until the timer is cancelled. Intervals below `timer::MIN_INTERVAL`
+	/// are stretched to it.
+	pub fn send_every(&mut self, interval: f64, make: Box<FnMut() -> Action + Send>) -> TimerId {
+		let id = self.take_timer_id();
+		let first = self.clock.get_time().total + interval.max(MIN_INTERVAL);
+		self.timers.add(Timer::every(id, first, interval, make));
+		return id;
+	}
+
+	/// Cancels a timer. Returns false if there was none with that id, e.g. because it already
+	/// fired. Stores can cancel timers through their context or with a `CancelTimerAction`.
+	pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+		self.timers.cancel(id)
+	}
+
+	/// Returns the number of timers waiting to fire.
+	pub fn get_timer_count(&self) -> usize {
+		self.timers.len()
+	}
+
+	fn take_timer_id(&mut self) -> TimerId {
+		let id = TimerId {
+			owner: None,
+			seq: self.next_timer_id,
+		};
+		self.next_timer_id += 1;
+		return id;
+	}
+
+	/// Hands actions of the given kinds to the stores entered with `add_send_store` in parallel,
+	/// spread over `workers` threads. Other stores still get them on the calling thread. What the
+	/// stores send is merged in store order, so the outcome is the same as without parallel
+	/// dispatch, as long as the stores don't depend on each other while handling these actions.
+	///
+	/// The threads are started here and kept until parallel dispatch is disabled (or enabled
+	/// again) or the dispatcher is dropped. With fewer than two workers, nothing runs in parallel.
+	pub fn enable_parallel_dispatch(&mut self, kinds: Vec<ActionKind>, workers: usize) {
+		self.parallel_kinds = kinds;
+		self.workers = match workers {
+			0 | 1 => None,
+			workers => Some(WorkerPool::new(workers)),
+		};
+	}
+
+	/// Goes back to calling every store on the calling thread.
+	pub fn disable_parallel_dispatch(&mut self) {
+		self.parallel_kinds.clear();
+		self.workers = None;
+	}
+
 	/// Works through the two stacks and dispatches the topmost
 	/// action of the current stack. Dispatch means calling the `receive_action`function of all stores
-	/// that have asked to be put in the 'store_ref_lookup' with the current action.
+	/// that have subscribed to the kind of the current action.
 	pub fn dispatch(&mut self) -> bool {
 		let in_action: Action;
 
+		match self.replay {
+			Some(ref replay) if replay.is_finished() => return false,
+			_ => (),
+		}
+		if self.stopped {
+			return false;
+		}
+
+		self.compact_stores();
+
 		match self.get_in_action() {
 			Some(a) => {
 				in_action = a;
@@ -57,92 +527,592 @@ impl<'a> Dispatcher<'a> {
 			None => return true,
 		}
 
+		for action in self.run_middleware(in_action) {
+			if !self.dispatch_action(action) {
+				return false;
+			}
+		}
+
+		return true;
+	}
+
+	/// Used in the dispatch function.
+	/// Passes an action through the middleware chain and returns what comes out at the end.
+	/// Whether player input gets dropped can depend on real time (see `DebounceMiddleware`), so
+	/// the recording notes which input was dropped and a replay drops the same input.
+	fn run_middleware(&mut self, in_action: Action) -> Vec<Action> {
+		let time = self.clock.get_time();
+		let input = copy_input(&in_action);
+		let mut actions = vec![in_action];
+		for middleware in self.middleware.iter_mut() {
+			let mut out_vec = vec![];
+			for action in actions {
+				out_vec.extend(middleware.process(action, &time));
+			}
+			actions = out_vec;
+		}
+
+		let input = match input {
+			Some(input) => input,
+			None => return actions,
+		};
+		self.middleware_inputs += 1;
+		match self.replay {
+			Some(ref mut replay) => {
+				if replay.was_input_dropped(self.middleware_inputs) {
+					return vec![];
+				}
+				if actions.is_empty() {
+					return vec![input];
+				}
+			}
+			None => {}
+		}
+		match self.recorder {
+			Some(ref mut recorder) if actions.is_empty() => recorder.record_dropped_input(self.middleware_inputs),
+			_ => {}
+		}
+		return actions;
+	}
+
+	/// Used in the dispatch function.
+	/// Dispatches a single action. Returns false if the game loop should stop.
+	fn dispatch_action(&mut self, in_action: Action) -> bool {
+		let mut in_action = match in_action {
+			Action::MenuAction(MenuSubAction::ChangeMenuStateAction) => match self.menu_state {
+				MenuState::NotInMenu => Action::MenuAction(MenuSubAction::PushMenuAction(0)),
+				MenuState::InMenu(_) => Action::MenuAction(MenuSubAction::PopMenuAction),
+			},
+			other => other,
+		};
+
 		match in_action {
 			Action::EndFrameAction => {
+				self.pace_frame();
 				self.use_secondary = false;
 				self.current_stack_start_time = Instant::now();
+				self.start_frame();
+				self.fire_timers();
+				let frame = self.clock.get_time().frame;
+				match self.profiler {
+					Some(ref mut profiler) => profiler.end_frame(frame),
+					None => (),
+				}
 			}
 			Action::MenuAction(ref sub) => {
 				match sub {
-					MenuSubAction::ChangeMenuStateAction => {
-						match self.menu_state {
-							MenuState::InMenu(delta) => {
-								self.current_stack_start_time = Instant::now() - delta;
-								self.menu_state = MenuState::NotInMenu;
-							}
-							MenuState::NotInMenu => {
-								self.menu_state = MenuState::InMenu(self.current_stack_start_time.elapsed())
-							}
-						}
+					MenuSubAction::PushMenuAction(scene) => {
+						self.push_menu(*scene);
+					}
+					MenuSubAction::PopMenuAction => {
+						self.pop_menu();
 					}
 					MenuSubAction::QuitAction => {
+						self.trace_action(&in_action);
 						return false;
 					}
 					_ => (),
 				};
 			}
 			Action::QuitAction => {
+				self.trace_action(&in_action);
 				return false;
 			}
+			Action::RemoveStoreAction(id) => {
+				self.remove_store(id);
+			}
+			Action::CancelTimerAction(id) => {
+				self.cancel_timer(id);
+			}
+			Action::VsyncAction(vsync) => {
+				self.vsync = vsync;
+			}
+			Action::TimeScaleAction(time_scale) => {
+				self.clock.set_time_scale(time_scale);
+			}
+			Action::StepFrameAction => {
+				self.clock.step_frame();
+			}
+			Action::SaveAction(ref path) => {
+				match self.save_to(path) {
+					Ok(_) => {}
+					Err(e) => frame_error!("Could not save to {}: {}", path.display(), e),
+				}
+			}
+			Action::LoadAction(ref path) => {
+				match self.load_from(path) {
+					Ok(_) => {}
+					Err(e) => frame_error!("Could not load {}: {}", path.display(), e),
+				}
+			}
 			_ => (),
 		}
 
-		match self.store_refs.take() {
+		match in_action {
+			Action::UpdateAction => {
+				// In fixed mode there may be several update steps due in one frame, or none at all.
+				for _ in 0..self.clock.take_update_steps() {
+					self.clock.advance_step();
+					self.fire_timers();
+					in_action = self.walk_stores(in_action);
+				}
+			}
+			Action::DirectedAction(_) => self.deliver(&in_action),
+			_ => {
+				self.walk_stores(in_action);
+			}
+		}
+
+		return !self.stopped;
+	}
+
+	/// Used in the dispatch function.
+	/// Waits for the rest of the frame if there is a target frame rate and no vsync.
+	fn pace_frame(&mut self) {
+		let frame_time = match self.target_frame_time {
+			Some(frame_time) if !self.vsync && self.simulated_frame_time.is_none() => frame_time,
+			_ => return,
+		};
+		let left = frame_time - self.current_stack_start_time.elapsed().as_secs_f64();
+		if left > PACING_MARGIN {
+			thread::sleep(Duration::from_secs_f64(left - PACING_MARGIN));
+		}
+		while self.current_stack_start_time.elapsed().as_secs_f64() < frame_time {
+			thread::yield_now();
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Opens a menu on top of the game or the open menus. The clock stops with the first one.
+	fn push_menu(&mut self, scene: usize) {
+		let layer = MenuLayer {
+			scene,
+			paused_at: self.current_stack_start_time.elapsed(),
+		};
+		match self.menu_state {
+			MenuState::InMenu(ref mut layers) => {
+				layers.push(layer);
+				return;
+			}
+			MenuState::NotInMenu => {}
+		}
+		self.menu_state = MenuState::InMenu(vec![layer]);
+		self.clock.pause();
+	}
+
+	/// Used in the dispatch function.
+	/// Closes the topmost menu and picks up the time where the layer below stopped. The clock
+	/// and the parked gameplay actions only go on once the last menu is closed.
+	fn pop_menu(&mut self) {
+		let empty = match self.menu_state {
+			MenuState::InMenu(ref mut layers) => {
+				match layers.pop() {
+					Some(layer) => self.current_stack_start_time = Instant::now() - layer.paused_at,
+					None => {}
+				}
+				layers.is_empty()
+			}
+			MenuState::NotInMenu => false,
+		};
+		if empty {
+			self.menu_state = MenuState::NotInMenu;
+			self.clock.resume();
+			while let Some(action) = self.parked_actions.pop_back() {
+				self.primary_action_queue.push_front(action);
+			}
+		}
+	}
+
+	/// Returns the open menus, the topmost one last. Empty while the game is running.
+	pub fn get_menu_layers(&self) -> &[MenuLayer] {
+		match self.menu_state {
+			MenuState::InMenu(ref layers) => layers,
+			MenuState::NotInMenu => &[],
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Hands the action to every store that has subscribed to its kind. Gives the action back
+	/// afterwards, as it's lent to the workers when walking in parallel.
+	fn walk_stores(&mut self, in_action: Action) -> Action {
+		self.trace_action(&in_action);
+		let kind = in_action.kind();
+		let route = match self.routes.remove(&kind) {
+			Some(route) => route,
+			None => self.build_route(kind),
+		};
+		let generation = self.store_generation;
+
+		let in_action = if self.workers.is_some() && self.parallel_kinds.contains(&kind) && !self.is_replayed_wait(&in_action) {
+			self.walk_parallel(in_action, &route)
+		} else {
+			self.walk_sequential(&in_action, &route);
+			in_action
+		};
+
+		if generation == self.store_generation {
+			self.routes.insert(kind, route);
+		}
+		return in_action;
+	}
+
+	/// Used in the walk_stores function.
+	/// Calls the stores one after another.
+	fn walk_sequential(&mut self, in_action: &Action, route: &Vec<usize>) {
+		let time = self.clock.get_time();
+
+		for &index in route.iter() {
 			/*
-			* This is where the double Option is used; Options have the take method,
-			* which lets the user get its value and replace the assigned memory with
-			* None. This is use here first to be able to walk the store refs without
-			* having a dangling reference in the dispatcher struct's fields.
+			* Stores removed by another store during this walk are only marked as removed,
+			* so the indices in the route stay valid until the next dispatch. Added stores
+			* go to the end and don't get the current action.
 			*/
-			Some(mut local_store_refs) => {
-				for index in 0..local_store_refs.len() {
-					match local_store_refs[index].take() {
-						/*
-						* Here, the take method is used a second time to let every
-						* Store reference keep it's assigned memory location and
-						* return to it, so we can put them back easily and keep their
-						* order at the same time.
-						*/
-						Some(in_reference) => {
-							self.dt = self.current_stack_start_time.elapsed().as_secs_f64();
-							match in_reference.receive_action(&in_action, &self.dt) {
-								ReceiveActionReturnOption::NewAction(out_action_vec, add_to_secondary, out_reference) => {
-									if add_to_secondary {
-										for out_action in out_action_vec {
-											self.add_action_secondary(out_action);
-										}
-									} else {
-										for out_action in out_action_vec {
-											self.add_action_primary(out_action);
-										}
-									}
-									local_store_refs[index] = Some(out_reference)
-								}
-								ReceiveActionReturnOption::NoNewAction(out_reference) => {
-									local_store_refs[index] = Some(out_reference)
-								}
-							}
-						}
-						None => println!("There's an empty store ref in your dispatcher, bröther ( ͡° ͜ʖ ͡°)")
+			if self.stopped {
+				break;
+			}
+			if self.store_entries[index].removed {
+				continue;
+			}
+			if self.store_entries[index].input_source && self.is_replayed_wait(in_action) {
+				for action in self.filter_inputs(vec![]) {
+					self.add_action_primary(action);
+				}
+				continue;
+			}
+
+			let ctx = self.make_context(index, time);
+			self.call_store(index, in_action, ctx);
+		}
+	}
+
+	/// Used in the walk_stores and walk_sequential functions.
+	/// Tells if the action has input sources wait for input in a menu while replaying. Nobody is at
+	/// the controls then, so instead of waiting, an input source counts as polled and the input
+	/// recorded for that poll is sent in its place.
+	fn is_replayed_wait(&self, in_action: &Action) -> bool {
+		match in_action {
+			Action::MenuAction(MenuSubAction::WaitForInputAction) => self.replay.is_some(),
+			_ => false,
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Hands a directed action to the only store it's meant for. Dropped if that store is gone.
+	fn deliver(&mut self, in_action: &Action) {
+		self.trace_action(in_action);
+		let envelope = match in_action {
+			Action::DirectedAction(ref envelope) => envelope,
+			_ => return,
+		};
+		let index = match self.store_entries.iter().position(|entry| entry.id == envelope.to && !entry.removed) {
+			Some(index) => index,
+			None => return,
+		};
+
+		let mut ctx = self.make_context(index, self.clock.get_time());
+		ctx.sender = envelope.from;
+		ctx.request = envelope.request;
+		ctx.reply_to = envelope.reply_to;
+		self.call_store(index, &envelope.action, ctx);
+	}
+
+	fn make_context(&self, index: usize, time: GameTime) -> Context {
+		let entry = &self.store_entries[index];
+		let mut ctx = Context::new(time, entry.id, self.next_store_id);
+		ctx.next_timer_seq = entry.next_timer_seq;
+		ctx.next_request_seq = entry.next_request_seq;
+		ctx.rng = self.rng.clone();
+		ctx.frame_time = self.frame_time;
+		return ctx;
+	}
+
+	/// Used in the walk_sequential and deliver functions.
+	/// Calls a single store on this thread and takes care of what it sent.
+	fn call_store(&mut self, index: usize, in_action: &Action, mut ctx: Context) {
+		let started = match self.profiler {
+			Some(_) => Some(Instant::now()),
+			None => None,
+		};
+		let result = self.store_entries[index].store.get_mut().receive_action(in_action, &mut ctx);
+		match (started, self.profiler.as_mut()) {
+			(Some(started), Some(profiler)) => profiler.record_store(self.store_entries[index].id, in_action.kind(), started),
+			_ => (),
+		}
+
+		let (added_stores, sent) = ctx.split();
+		self.rng = sent.rng.clone();
+		self.apply_sent(index, added_stores, sent);
+		match result {
+			Ok(_) => {}
+			Err(error) => self.handle_error(index, in_action, error),
+		}
+	}
+
+	/// Used in the call_store and walk_parallel functions.
+	/// Deals with a store that failed to handle an action, according to the error policy.
+	fn handle_error(&mut self, index: usize, in_action: &Action, error: String) {
+		let id = self.store_entries[index].id;
+		frame_error!("Store {} failed to handle {:?}: {}", id.0, in_action.kind(), error);
+		match self.error_policy {
+			ErrorPolicy::LogAndContinue => {}
+			ErrorPolicy::DisableStore => self.mark_removed(id),
+			ErrorPolicy::StopLoop => {
+				self.stopped = true;
+				return;
+			}
+		}
+		// A store failing on an error action doesn't send another one, or errors could go round
+		// forever.
+		match in_action {
+			Action::StoreErrorAction(..) => {}
+			_ => self.add_action_primary(Action::StoreErrorAction(id, error)),
+		}
+	}
+
+	/// Used in the walk_stores function.
+	/// Hands the action to the `Send` stores on the worker threads while the other stores get it
+	/// on this thread. Afterwards, what the stores sent is taken care of in store order, as if
+	/// they had run one after another. All stores in the route get the action, even if one of them
+	/// removes another, but once one of them stops the game loop, what the stores after it sent is
+	/// dropped. Every store draws from its own fork of the random number generator.
+	fn walk_parallel(&mut self, in_action: Action, route: &Vec<usize>) -> Action {
+		let time = self.clock.get_time();
+		let in_action = Arc::new(in_action);
+		let mut local = vec![];
+		let mut lent = 0;
+		{
+			let pool = match self.workers {
+				Some(ref pool) => pool,
+				None => unreachable!("Walking in parallel without workers"),
+			};
+			for &index in route.iter() {
+				if self.store_entries[index].removed {
+					continue;
+				}
+				let rng = self.rng.fork();
+				let entry = &mut self.store_entries[index];
+				match mem::replace(&mut entry.store, StoreSlot::Lent) {
+					StoreSlot::Shared(store) => {
+						let job = Job {
+							index,
+							id: entry.id,
+							store,
+							action: in_action.clone(),
+							time,
+							frame_time: self.frame_time,
+							next_store_id: self.next_store_id,
+							next_timer_seq: entry.next_timer_seq,
+							next_request_seq: entry.next_request_seq,
+							rng,
+						};
+						pool.jobs[lent % pool.jobs.len()].send(job).expect("A worker thread is gone");
+						lent += 1;
+					}
+					other => {
+						entry.store = other;
+						local.push((index, rng));
 					}
 				}
-				self.store_refs = Some(local_store_refs)
 			}
-			None => println!("No store refs yet ( ͡° ͜ʖ ͡°)")
 		}
 
-		return true;
+		let mut results = vec![];
+		for (index, rng) in local {
+			let mut ctx = self.make_context(index, time);
+			ctx.rng = rng;
+			let started = Instant::now();
+			let result = self.store_entries[index].store.get_mut().receive_action(&in_action, &mut ctx);
+			let duration = started.elapsed();
+			let (added_stores, sent) = ctx.split();
+			results.push((index, self.store_entries[index].id, added_stores, sent, result, started, duration));
+		}
+
+		let mut panic = None;
+		for _ in 0..lent {
+			let job_result = match self.workers {
+				Some(ref pool) => pool.results.recv().expect("A worker thread is gone"),
+				None => unreachable!("Walking in parallel without workers"),
+			};
+			self.store_entries[job_result.index].store = StoreSlot::Shared(job_result.store);
+			if panic.is_none() {
+				panic = job_result.panic;
+			}
+			results.push((job_result.index, job_result.id, vec![], job_result.sent, job_result.result, job_result.started, job_result.duration));
+		}
+		match panic {
+			Some(payload) => panic::resume_unwind(payload),
+			None => {}
+		}
+		let in_action = match Arc::try_unwrap(in_action) {
+			Ok(action) => action,
+			Err(_) => unreachable!("A worker kept the action"),
+		};
+
+		results.sort_by_key(|result| result.0);
+		let kind = in_action.kind();
+		for (index, id, added_stores, sent, result, started, duration) in results {
+			if self.stopped {
+				break;
+			}
+			match self.profiler {
+				Some(ref mut profiler) => profiler.record_store_call(id, kind, started, duration),
+				None => (),
+			}
+			self.apply_sent(index, added_stores, sent);
+			match result {
+				Ok(_) => {}
+				Err(error) => self.handle_error(index, &in_action, error),
+			}
+		}
+		return in_action;
+	}
+
+	/// Used in the walk_stores function.
+	/// Takes care of everything the store at `index` sent through its context.
+	fn apply_sent(&mut self, index: usize, added_stores: Vec<(StoreId, Box<Store>)>, mut sent: Sent) {
+		if self.store_entries[index].input_source {
+			sent.primary = self.filter_inputs(sent.primary);
+		}
+		self.store_entries[index].next_timer_seq = sent.next_timer_seq;
+		self.store_entries[index].next_request_seq = sent.next_request_seq;
+
+		for out_action in sent.primary {
+			self.add_action_primary(out_action);
+		}
+		for out_action in sent.secondary {
+			self.add_action_secondary(out_action);
+		}
+		for (id, store) in added_stores {
+			if id.0 >= self.next_store_id {
+				self.next_store_id = id.0 + 1;
+			}
+			self.insert_store(id, StoreSlot::Local(store));
+		}
+		for id in sent.removed_stores {
+			self.mark_removed(id);
+		}
+		for timer in sent.timers {
+			self.timers.add(timer);
+		}
+		for id in sent.cancelled_timers {
+			self.timers.cancel(id);
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Moves the actions of all due timers to the primary queue.
+	fn fire_timers(&mut self) {
+		let time = self.clock.get_time();
+		for action in self.timers.fire(&time) {
+			self.add_action_primary(action);
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Starts a new frame on the clock. When replaying, the frame takes as long as it did in the
+	/// recording, otherwise as long as the simulated frame time if there is one.
+	fn start_frame(&mut self) {
+		let recorded_elapsed = match self.replay {
+			Some(ref replay) => replay.get_frame_elapsed(self.clock.get_time().frame + 1),
+			None => None,
+		};
+		let elapsed = match recorded_elapsed.or(self.simulated_frame_time) {
+			Some(elapsed) => {
+				self.clock.start_frame_with(elapsed);
+				elapsed
+			}
+			None => self.clock.start_frame(),
+		};
+		self.frame_time = elapsed;
+		match self.recorder {
+			Some(ref mut recorder) => recorder.record_frame(self.clock.get_time().frame, elapsed),
+			None => (),
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Writes a dispatched action to the recording, or checks it against the replay.
+	fn trace_action(&mut self, action: &Action) {
+		let time = self.clock.get_time();
+		match self.recorder {
+			Some(ref mut recorder) => recorder.record_action(&time, action),
+			None => (),
+		}
+		match self.replay {
+			Some(ref mut replay) => replay.check_action(&time, action),
+			None => (),
+		}
+	}
+
+	/// Used in the dispatch function.
+	/// Records the player input sent by an input source, or swaps it for the recorded input when
+	/// replaying. All other actions are let through as they are.
+	fn filter_inputs(&mut self, actions: Vec<Action>) -> Vec<Action> {
+		self.input_polls += 1;
+		match self.replay {
+			Some(ref mut replay) => {
+				let mut out_vec = replay.take_inputs(self.input_polls);
+				for action in actions {
+					if !is_input(&action) {
+						out_vec.push(action);
+					}
+				}
+				return out_vec;
+			}
+			None => {}
+		}
+		match self.recorder {
+			Some(ref mut recorder) => {
+				for action in actions.iter() {
+					if is_input(action) {
+						recorder.record_input(self.input_polls, action);
+					}
+				}
+			}
+			None => {}
+		}
+		return actions;
+	}
+
+	/// Used in the dispatch function.
+	/// Collects the indices of all stores that want to receive actions of the given kind.
+	fn build_route(&self, kind: ActionKind) -> Vec<usize> {
+		let mut route = vec![];
+		for (index, entry) in self.store_entries.iter().enumerate() {
+			if entry.removed {
+				continue;
+			}
+			match entry.subscriptions {
+				Some(ref kinds) => {
+					if kinds.contains(&kind) {
+						route.push(index);
+					}
+				}
+				None => route.push(index),
+			}
+		}
+		return route;
 	}
 
 	/// Used in the dispatch function.
 	/// returns the appropriate action from the appropriate Stack.
 	fn get_in_action(&mut self) -> Option<Action> {
+		match self.menu_state {
+			MenuState::InMenu(_) => return Some(self.get_menu_action()),
+			MenuState::NotInMenu => {}
+		}
+
 		if self.use_secondary {
-			if self.current_stack_start_time.elapsed().as_secs_f64() >= self.max_stack_time {
+			if self.is_out_of_time() {
+				self.note_forced_end();
 				return Some(Action::EndFrameAction);
 			} else {
 				match self.secondary_action_queue.pop_front() {
 					Some(x) => {
+						match self.profiler {
+							Some(ref mut profiler) => profiler.record_secondary_drained(),
+							None => (),
+						}
 						return Some(x);
 					}
 					None => {
@@ -151,57 +1121,85 @@ impl<'a> Dispatcher<'a> {
 				}
 			}
 		} else {
-			match self.menu_state {
-				MenuState::NotInMenu => {
-					match self.primary_action_queue.pop_front() {
-						Some(x) => {
-							match x {
-								Action::MenuAction(sub) => {
-									match sub {
-										MenuSubAction::ChangeMenuStateAction => {
-											return Some(MenuAction(MenuSubAction::ChangeMenuStateAction));
-										}
-										_ => return None
-									}
+			match self.primary_action_queue.pop_front() {
+				Some(x) => {
+					match x {
+						Action::MenuAction(sub) => {
+							match sub {
+								MenuSubAction::ChangeMenuStateAction
+								| MenuSubAction::PushMenuAction(_)
+								| MenuSubAction::QuitAction => return Some(MenuAction(sub)),
+								// Closing a menu that is closed already, e.g. twice in a row.
+								MenuSubAction::PopMenuAction => {
+									frame_debug!("No menu to close");
+									return None;
+								}
+								// Left over from a menu that has been closed already.
+								_ => {
+									frame_warn!("Dropped {} as no menu is open", encode_action(&MenuAction(sub)));
+									return None;
 								}
-								_ => return Some(x),
-							}
-						}
-						None => {
-							if self.current_stack_start_time.elapsed().as_secs_f64() >= self.max_stack_time {
-								return Some(Action::EndFrameAction);
-							} else {
-								self.use_secondary = true;
-								return None;
 							}
 						}
+						_ => return Some(x),
 					}
 				}
-				MenuState::InMenu(time) => {
-					let mut remove_index: Option<usize> = None;
-					for index in 0..(self.primary_action_queue.len() - 1) {
-						match self.primary_action_queue[index] {
-							Action::MenuAction(ref sub) => {
-								remove_index = Some(index);
-								break;
-							}
-							_ => (),
-						};
-					}
-					match remove_index {
-						Some(i) => match self.primary_action_queue.remove(i) {
-							Some(action) => return Some(action),
-							None => return None,
-						}
-						None => {
-							return Some(Action::MenuAction(MenuSubAction::WaitForInputAction));
-						}
+				None => {
+					if self.is_out_of_time() {
+						self.note_forced_end();
+						return Some(Action::EndFrameAction);
+					} else {
+						self.use_secondary = true;
+						return None;
 					}
 				}
 			}
 		}
 	}
 
+	/// Used in the get_in_action function.
+	/// Takes the next menu action from the primary queue, in the order they were sent. Gameplay
+	/// actions in front of it are parked until the last menu is closed, only a `QuitAction` goes
+	/// through right away. If there is no menu action, the menu waits for input.
+	fn get_menu_action(&mut self) -> Action {
+		loop {
+			match self.primary_action_queue.pop_front() {
+				Some(Action::MenuAction(sub)) => return Action::MenuAction(sub),
+				Some(Action::QuitAction) => return Action::QuitAction,
+				Some(action) => self.parked_actions.push_back(action),
+				None => return Action::MenuAction(MenuSubAction::WaitForInputAction),
+			}
+		}
+	}
+
+	/// Used in the get_in_action function.
+	/// Tells if the frame has taken longer than the maximum stack time. Simulated frames never do.
+	fn is_out_of_time(&self) -> bool {
+		match self.simulated_frame_time {
+			Some(_) => false,
+			None => self.current_stack_start_time.elapsed().as_secs_f64() >= self.max_stack_time,
+		}
+	}
+
+	/// Tells if everything sent during the current frame has been dispatched, so the next dispatch
+	/// ends the frame (or finds out that it has to). Always false while a menu is open.
+	pub fn is_frame_done(&self) -> bool {
+		match self.menu_state {
+			MenuState::InMenu(_) => false,
+			MenuState::NotInMenu => self.primary_action_queue.is_empty() && self.secondary_action_queue.is_empty(),
+		}
+	}
+
+	/// Used in the get_in_action function.
+	/// Tells the profiler that the frame ran out of time.
+	fn note_forced_end(&mut self) {
+		let left = self.secondary_action_queue.len();
+		match self.profiler {
+			Some(ref mut profiler) => profiler.record_forced_end(left),
+			None => (),
+		}
+	}
+
 	///Adds an action to the primary stack.
 	pub fn add_action_primary(&mut self, action: Action) {
 		self.primary_action_queue.push_back(action)
@@ -212,30 +1210,177 @@ impl<'a> Dispatcher<'a> {
 		self.secondary_action_queue.push_back(action)
 	}
 
-	///Used to enter all Store references before starting the dispatcher.
-	pub fn enter_refs(&mut self, references: Vec<&'a mut Store<'a>>) {
-		let mut local_store_refs: Vec<Option<&'a mut Store<'a>>>;
+	/// Sends an action to a single store, see `Context::send_to`.
+	pub fn send_to(&mut self, to: StoreId, action: Action) {
+		self.add_action_primary(Action::DirectedAction(Envelope {
+			to,
+			from: None,
+			request: None,
+			reply_to: None,
+			action: Box::new(action),
+		}));
+	}
 
-		match self.store_refs.take() {
-			Some(x) => {
-				local_store_refs = x;
+	///Used to enter all Stores before starting the dispatcher. Returns the ids the stores got, in
+	/// the same order.
+	pub fn enter_stores(&mut self, stores: Vec<Box<Store>>) -> Vec<StoreId> {
+		let mut ids = vec![];
+		for store in stores {
+			ids.push(self.add_store(store));
+		}
+		return ids;
+	}
+
+	/// Enters a single store. Works while the game loop is running, too: the store receives
+	/// everything dispatched from the next action on. Stores can add other stores through their
+	/// context.
+	pub fn add_store(&mut self, store: Box<Store>) -> StoreId {
+		let id = StoreId(self.next_store_id);
+		self.next_store_id += 1;
+		self.insert_store(id, StoreSlot::Local(store));
+		return id;
+	}
+
+	/// Enters a store that may receive actions on a worker thread, see
+	/// `enable_parallel_dispatch`. Otherwise works just like `add_store`.
+	pub fn add_send_store(&mut self, store: Box<Store + Send>) -> StoreId {
+		let id = StoreId(self.next_store_id);
+		self.next_store_id += 1;
+		self.insert_store(id, StoreSlot::Shared(store));
+		return id;
+	}
+
+	/// Takes a store out of the dispatcher and hands it back. Stores can remove each other (or
+	/// themselves) through their context or with a `RemoveStoreAction`.
+	pub fn remove_store(&mut self, id: StoreId) -> Option<Box<Store>> {
+		match self.store_entries.iter().position(|entry| entry.id == id && !entry.removed) {
+			Some(index) => {
+				self.store_generation += 1;
+				self.routes.clear();
+				Some(self.store_entries.remove(index).store.into_store())
 			}
-			_ => {
-				local_store_refs = vec![];
+			None => None,
+		}
+	}
+
+	/// Collects a snapshot from every store that has a save key, and the state of the random
+	/// number generator.
+	pub fn take_snapshot(&self) -> SaveFile {
+		let mut save = SaveFile::new();
+		save.set_rng_state(self.rng.get_state());
+		for entry in self.store_entries.iter() {
+			if entry.removed {
+				continue;
+			}
+			let store = entry.store.get();
+			match store.save_key() {
+				Some(key) => save.insert(key, store.save()),
+				None => {}
 			}
 		}
+		return save;
+	}
 
-		for reference in references {
-			local_store_refs.push(Some(reference));
+	/// Hands the snapshots to the stores with a matching save key and puts the random number
+	/// generator back to the saved state. Stores without a snapshot are left as they are. Every
+	/// store gets its snapshot even if another one fails to load, the first error is returned.
+	pub fn restore_snapshot(&mut self, save: &SaveFile) -> Result<(), String> {
+		match save.get_rng_state() {
+			Some(state) => self.rng.set_state(state),
+			None => {}
 		}
+		let mut result = Ok(());
+		for entry in self.store_entries.iter_mut() {
+			if entry.removed {
+				continue;
+			}
+			let store = entry.store.get_mut();
+			let key = match store.save_key() {
+				Some(key) => key,
+				None => continue,
+			};
+			match save.get(&key) {
+				Some(data) => match store.load(data) {
+					Ok(_) => {}
+					Err(e) => if result.is_ok() {
+						result = Err(format!("{}: {}", key, e));
+					},
+				},
+				None => {}
+			}
+		}
+		return result;
+	}
 
-		self.store_refs = Some(local_store_refs);
+	/// Writes the snapshots of all stores with a save key to a file. Stores ask for this by
+	/// sending a `SaveAction`. Timers aren't saved.
+	pub fn save_to<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		self.take_snapshot().save(path)
 	}
 
-	///Used to drop all Store references before ending the game loop
-	pub fn drop_refs(&mut self) {
-		match self.store_refs.take(){
-			_ => {}
+	/// Restores the stores from a file written by `save_to`. Stores ask for this by sending a
+	/// `LoadAction`.
+	pub fn load_from<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+		let save = SaveFile::open(path)?;
+		match self.restore_snapshot(&save) {
+			Ok(_) => Ok(()),
+			Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+		}
+	}
+
+	/// Returns the ids of all stores currently in the dispatcher, in the order they receive
+	/// actions.
+	pub fn get_store_ids(&self) -> Vec<StoreId> {
+		let mut ids = vec![];
+		for entry in self.store_entries.iter() {
+			if !entry.removed {
+				ids.push(entry.id);
+			}
+		}
+		return ids;
+	}
+
+	fn insert_store(&mut self, id: StoreId, store: StoreSlot) {
+		self.store_entries.push(StoreEntry {
+			id,
+			subscriptions: store.get().subscriptions(),
+			input_source: store.get().is_input_source(),
+			next_timer_seq: 0,
+			next_request_seq: 0,
+			store,
+			removed: false,
+		});
+		self.store_generation += 1;
+		self.routes.clear();
+	}
+
+	/// Used while walking the stores, where the indices in the route have to stay valid.
+	fn mark_removed(&mut self, id: StoreId) {
+		for entry in self.store_entries.iter_mut() {
+			if entry.id == id {
+				entry.removed = true;
+			}
+		}
+		self.store_generation += 1;
+		self.routes.clear();
+	}
+
+	/// Used in the dispatch function.
+	/// Throws out the stores that were removed since the last dispatch.
+	fn compact_stores(&mut self) {
+		if !self.store_entries.iter().any(|entry| entry.removed) {
+			return;
 		}
+		self.store_entries.retain(|entry| !entry.removed);
+		self.store_generation += 1;
+		self.routes.clear();
+	}
+
+	///Used to drop all Stores before ending the game loop
+	pub fn drop_stores(&mut self) {
+		self.store_entries.clear();
+		self.routes.clear();
+		self.store_generation += 1;
+		self.stop_recording();
 	}
 }
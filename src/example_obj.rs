@@ -1,70 +1,56 @@
-use action::{Action, MenuSubAction};
-use dispatcher::Dispatcher;
-use img::Img;
+use action::{Action, ActionKind, Depth, Layer};
 use sprite::Sprite;
-use store::ReceiveActionReturnOption;
-use store::Store;
+use store::Context;
+use store::{Store, StoreResult};
 
 ///A simple implementation of an animated game object
 pub struct ExampleObj {
-	sprite: Option<Sprite>,
+	sprite: Sprite,
 	accumulated_dt: f64,
 }
 
-impl<'a> ExampleObj {
+impl ExampleObj {
 	pub fn new(sprite: Sprite) -> Self {
 		Self {
-			sprite: Some(sprite),
+			sprite,
 			accumulated_dt: 0.0,
 		}
 	}
 
-	fn send_frame(&'a mut self, dt: f64) -> Action {
-		let mut out_action = Action::EmptyAction;
-
-		match self.sprite.take() {
-			Some(mut sprite) => {
-				sprite.animate(dt);
-				out_action = Action::AddImgToCanvasAction(
-					sprite.get_pos().0.clone(),
-					sprite.get_pos().1.clone(),
-					sprite.get_current_frame(),
-				);
-				self.sprite = Some(sprite);
-			}
-			None => {}
-		}
-		return out_action;
+	fn send_frame(&mut self, dt: f64) -> Action {
+		self.sprite.animate(dt);
+		return Action::AddImgToCanvasAction(
+			self.sprite.get_pos().0 as i32,
+			self.sprite.get_pos().1 as i32,
+			Depth::new(Layer::World, 0),
+			self.sprite.get_current_frame(),
+		);
 	}
 }
 
-impl<'a> Store<'a> for ExampleObj {
-	fn receive_action(&'a mut self, action: &Action, dt: &f64) -> ReceiveActionReturnOption<'a> {
+impl Store for ExampleObj {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
 		match action {
 			&Action::SendFrameAction => {
-				return ReceiveActionReturnOption::NewAction(
-					vec!(
-						self.send_frame(dt.clone())
-					),
-					false,
-					self,
-				);
+				let dt = ctx.get_time().dt;
+				ctx.send(self.send_frame(dt));
+			}
+			&Action::AssetReloadAction(ref path, ref bytes) => {
+				self.sprite.reload_asset(path, bytes);
 			}
 			&Action::UpdateAction => {
-				self.accumulated_dt += dt.clone();
-				match self.sprite.take() {
-					Some(mut sprite) => {
-						sprite.set_pos((
-							(self.accumulated_dt * 300.0) as u8,
-							(self.accumulated_dt * 300.0) as u8
-						));
-						self.sprite = Some(sprite);
-					}
-					None => {}
-				}
-				return ReceiveActionReturnOption::NoNewAction(self);
+				self.accumulated_dt += ctx.get_time().dt;
+				self.sprite.set_pos((
+					(self.accumulated_dt * 300.0) as u8 as u32,
+					(self.accumulated_dt * 300.0) as u8 as u32
+				));
 			}
-			_ => return ReceiveActionReturnOption::NoNewAction(self)
+			_ => {}
 		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::SendFrameAction, ActionKind::UpdateAction, ActionKind::AssetReloadAction])
 	}
 }
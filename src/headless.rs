@@ -0,0 +1,30 @@
+use super::action::{Action, ActionKind};
+use super::store::{Context, Store, StoreResult};
+
+/// Stands in for the renderer when there is none, e.g. in simulations and tests: starts the
+/// update and the `SendFrameAction` of every frame, just like the renderer does. Nothing is drawn
+/// and there is no player input.
+pub struct HeadlessDriver;
+
+impl HeadlessDriver {
+	pub fn new() -> Self {
+		HeadlessDriver
+	}
+}
+
+impl Store for HeadlessDriver {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::EndFrameAction => {
+				ctx.send(Action::UpdateAction);
+				ctx.send(Action::SendFrameAction);
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::EndFrameAction])
+	}
+}
@@ -1,5 +1,8 @@
 use std::fs::File;
 use std::io::Read;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// An Image defined by it's width and a vector of pixels, made up of 4-bit unsigned ints (stored
 /// as u8 for memory efficiency). The image height is implied through the length of the vector.
@@ -21,14 +24,18 @@ impl Img {
 
 	/// Reads file into Img.
 	pub fn new_from_file(mut f: File) -> Self {
-		let mut width = 0;
-		let mut pixels = vec![];
-
 		let mut buf: Vec<u8> = vec![];
 		match f.read_to_end(&mut buf) {
 			Ok(_) => {},
-			Err(e) => eprintln!("Could not open image buffer:{}", e),
+			Err(e) => frame_error!("Could not open image buffer: {}", e),
 		}
+		return Self::new_from_bytes(&buf);
+	}
+
+	/// Reads the contents of a .u4i file into Img, e.g. when an asset was reloaded.
+	pub fn new_from_bytes(buf: &[u8]) -> Self {
+		let mut width = 0;
+		let mut pixels = vec![];
 
 		if buf.len() < 4 {
 			return Self {
@@ -69,6 +76,24 @@ impl Img {
 	/// Returns length of pixel vector
 	pub fn get_length(&self) -> usize { *&self.pixels.len().clone() }
 
+	/// Returns the color space indices of the pixels, row by row, without moving the iterator. The
+	/// last row can be shorter than the others.
+	pub fn get_index_rows(&self) -> Vec<Vec<u8>> {
+		let width = self.width / 2;
+		if width == 0 {
+			return vec![];
+		}
+		let mask: u8 = 0b00001111;
+		self.pixels.chunks(width).map(|row| {
+			let mut indices = Vec::with_capacity(row.len() * 2);
+			for pixel_pair in row.iter() {
+				indices.push((pixel_pair >> 4) & mask);
+				indices.push(pixel_pair & mask);
+			}
+			indices
+		}).collect()
+	}
+
 }
 
 impl Iterator for Img {
@@ -108,3 +133,42 @@ impl Clone for Img {
 		}
 	}
 }
+
+static NEXT_IMG_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A shared, read-only Img. Cloning a handle doesn't copy the pixels, so handles are what sprites
+/// keep and what draw actions carry. Every handle made with `new` gets its own id, which renderer
+/// backends use to upload the image once and reuse it for as long as the handle is drawn.
+#[derive(Clone)]
+pub struct ImgHandle {
+	id: u64,
+	img: Arc<Img>,
+}
+
+impl ImgHandle {
+	pub fn new(img: Img) -> Self {
+		Self {
+			id: NEXT_IMG_ID.fetch_add(1, Ordering::Relaxed),
+			img: Arc::new(img),
+		}
+	}
+
+	/// Returns the id shared by all clones of this handle.
+	pub fn get_id(&self) -> u64 {
+		self.id
+	}
+}
+
+impl Deref for ImgHandle {
+	type Target = Img;
+
+	fn deref(&self) -> &Img {
+		&self.img
+	}
+}
+
+impl From<Img> for ImgHandle {
+	fn from(img: Img) -> Self {
+		Self::new(img)
+	}
+}
@@ -3,13 +3,27 @@
 extern crate rand;
 extern crate sdl2;
 
+#[macro_use]
+pub mod log;
 pub mod dispatcher;
+pub mod middleware;
 pub mod action;
+pub mod clock;
+pub mod timer;
+pub mod recorder;
+pub mod save;
+pub mod rng;
+pub mod profiler;
 pub mod store;
 pub mod renderer;
+pub mod camera;
+pub mod render_backend;
+pub mod texture_cache;
 pub mod sprite;
 pub mod test_object;
 pub mod img;
+pub mod asset_watcher;
+pub mod headless;
 pub mod button;
 pub mod scene_manager;
 
@@ -17,12 +31,61 @@ pub mod scene_manager;
 mod tests;
 
 /// "Front-end"-function of the dispatcher
-pub fn game_loop<'a>(disp: & mut dispatcher::Dispatcher<'a>, store_refs: Vec<&'a mut store::Store<'a>>) {
+pub fn game_loop(disp: & mut dispatcher::Dispatcher, stores: Vec<Box<store::Store>>) {
 
-		disp.enter_refs(store_refs);
+		disp.enter_stores(stores);
 
 		while disp.dispatch() {};
 
-		disp.drop_refs();
+		disp.drop_stores();
 
 }
+
+/// Runs the game for the given number of frames, then hands back control. Unlike `game_loop`, the
+/// stores are neither entered nor dropped, so the game can be looked at and run on afterwards.
+///
+/// Returns false if the game stopped before, e.g. because of a `QuitAction`. Use a
+/// `headless::HeadlessDriver` in place of a renderer and `Dispatcher::set_simulated_frame_time`
+/// to run faster than real time.
+///
+/// No frames pass while a menu is open, so it also returns (true) as soon as a menu opens. Check
+/// `Dispatcher::get_menu_layers` to tell the two apart.
+pub fn run_frames(disp: &mut dispatcher::Dispatcher, frames: u64) -> bool {
+	if frames == 0 {
+		return true;
+	}
+	let last = disp.get_clock().get_time().frame + frames;
+	return run_until(disp, |disp| disp.get_clock().get_time().frame >= last || !disp.get_menu_layers().is_empty());
+}
+
+/// Runs the game until `done` returns true, then hands back control like `run_frames`. `done` is
+/// asked once at the end of every frame: after everything sent during the frame was dispatched,
+/// or after the `EndFrameAction` if the frame ran out of time first. It's also asked right away
+/// if the current frame is done already.
+///
+/// While a menu is open, `done` is asked before every dispatch instead, since frames only go on
+/// once the menu is closed. Without a store to close it, that's the only way out.
+pub fn run_until<F: FnMut(&mut dispatcher::Dispatcher) -> bool>(disp: &mut dispatcher::Dispatcher, mut done: F) -> bool {
+	let mut asked_frame = None;
+	let mut last_frame = disp.get_clock().get_time().frame;
+	loop {
+		let frame = disp.get_clock().get_time().frame;
+		let ask = if frame != last_frame && asked_frame != Some(last_frame) {
+			// The last frame ended without being done.
+			asked_frame = Some(last_frame);
+			true
+		} else if disp.is_frame_done() && asked_frame != Some(frame) {
+			asked_frame = Some(frame);
+			true
+		} else {
+			!disp.get_menu_layers().is_empty()
+		};
+		last_frame = frame;
+		if ask && done(disp) {
+			return true;
+		}
+		if !disp.dispatch() {
+			return false;
+		}
+	}
+}
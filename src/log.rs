@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How important a log message is. The earlier in this list, the more important.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Level {
+	Error,
+	Warn,
+	Info,
+	Debug,
+	Trace,
+}
+
+impl fmt::Display for Level {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let name = match *self {
+			Level::Error => "ERROR",
+			Level::Warn => "WARN",
+			Level::Info => "INFO",
+			Level::Debug => "DEBUG",
+			Level::Trace => "TRACE",
+		};
+		f.write_str(name)
+	}
+}
+
+/// A single log message.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Record {
+	pub level: Level,
+	/// Where the message comes from. The logging macros use the module path, e.g.
+	/// `frame::dispatcher`.
+	pub target: String,
+	pub message: String,
+}
+
+impl fmt::Display for Record {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "[{} {}] {}", self.level, self.target, self.message)
+	}
+}
+
+/// Somewhere log messages end up. Sinks are called while the logger is locked, so they must not
+/// log themselves.
+pub trait Sink: Send {
+	fn write(&mut self, record: &Record);
+
+	/// Writes out anything the sink buffers.
+	fn flush(&mut self) {}
+}
+
+/// Writes every message to stderr. This is where messages go until other sinks are set.
+pub struct StderrSink;
+
+impl Sink for StderrSink {
+	fn write(&mut self, record: &Record) {
+		let stderr = io::stderr();
+		let _ = writeln!(stderr.lock(), "{}", record);
+	}
+}
+
+/// Appends every message to a file, one line each.
+pub struct FileSink {
+	out: LineWriter<File>,
+}
+
+impl FileSink {
+	/// Opens the file for appending, creating it if it doesn't exist.
+	pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		Ok(Self {
+			out: LineWriter::new(file),
+		})
+	}
+}
+
+impl Sink for FileSink {
+	fn write(&mut self, record: &Record) {
+		let _ = writeln!(self.out, "{}", record);
+	}
+
+	fn flush(&mut self) {
+		let _ = self.out.flush();
+	}
+}
+
+/// Keeps the last `capacity` messages in memory, e.g. for a debug console or for tests. Clones
+/// share the same messages, so keep one to read them and hand the other to `add_sink`.
+#[derive(Clone)]
+pub struct RingBuffer {
+	records: Arc<Mutex<VecDeque<Record>>>,
+	capacity: usize,
+}
+
+impl RingBuffer {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+			capacity,
+		}
+	}
+
+	/// Returns the kept messages, the oldest one first.
+	pub fn get_records(&self) -> Vec<Record> {
+		lock(&self.records).iter().cloned().collect()
+	}
+
+	pub fn clear(&self) {
+		lock(&self.records).clear();
+	}
+}
+
+impl Sink for RingBuffer {
+	fn write(&mut self, record: &Record) {
+		if self.capacity == 0 {
+			return;
+		}
+		let mut records = lock(&self.records);
+		if records.len() == self.capacity {
+			records.pop_front();
+		}
+		records.push_back(record.clone());
+	}
+}
+
+struct Logger {
+	level: Level,
+	targets: Vec<(String, Level)>,
+	sinks: Option<Vec<Box<Sink>>>,
+	// None until sinks are set, which means stderr.
+}
+
+impl Logger {
+	/// The level for a target is the one set for the longest prefix of it, or the global one.
+	fn get_level(&self, target: &str) -> Level {
+		let mut level = self.level;
+		let mut matched = 0;
+		for &(ref prefix, prefix_level) in self.targets.iter() {
+			if is_in_target(target, prefix) && prefix.len() >= matched {
+				level = prefix_level;
+				matched = prefix.len();
+			}
+		}
+		return level;
+	}
+
+	fn get_sinks(&mut self) -> &mut Vec<Box<Sink>> {
+		if self.sinks.is_none() {
+			self.sinks = Some(vec![Box::new(StderrSink)]);
+		}
+		self.sinks.as_mut().unwrap()
+	}
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+	level: Level::Info,
+	targets: Vec::new(),
+	sinks: None,
+});
+
+/// A panicking sink shouldn't take logging down with it.
+fn lock<'a, T>(mutex: &'a Mutex<T>) -> MutexGuard<'a, T> {
+	match mutex.lock() {
+		Ok(guard) => guard,
+		Err(poisoned) => poisoned.into_inner(),
+	}
+}
+
+/// Whether `target` is `prefix` or one of its submodules.
+fn is_in_target(target: &str, prefix: &str) -> bool {
+	target == prefix || (target.starts_with(prefix) && target[prefix.len()..].starts_with("::"))
+}
+
+/// Sets the least important level that is still logged. The default is `Level::Info`.
+pub fn set_level(level: Level) {
+	lock(&LOGGER).level = level;
+}
+
+/// Sets the level for a target and everything below it, e.g. `Level::Trace` for
+/// `frame::dispatcher`. Overrides the global level and the levels of shorter targets.
+pub fn set_target_level(target: &str, level: Level) {
+	let mut logger = lock(&LOGGER);
+	logger.targets.retain(|entry| entry.0 != target);
+	logger.targets.push((String::from(target), level));
+}
+
+/// Replaces all sinks. An empty list silences the log.
+pub fn set_sinks(sinks: Vec<Box<Sink>>) {
+	lock(&LOGGER).sinks = Some(sinks);
+}
+
+/// Adds a sink next to the ones already there.
+pub fn add_sink(sink: Box<Sink>) {
+	lock(&LOGGER).get_sinks().push(sink);
+}
+
+/// Whether a message of that level from that target would be logged.
+pub fn is_enabled(level: Level, target: &str) -> bool {
+	level <= lock(&LOGGER).get_level(target)
+}
+
+/// Hands a message to all sinks, if its level is enabled for the target. Usually called through
+/// the `frame_error!`, `frame_warn!`, `frame_info!`, `frame_debug!` and `frame_trace!` macros,
+/// which are prefixed so they don't clash with the macros of the `log` crate.
+pub fn log(level: Level, target: &str, args: fmt::Arguments) {
+	let mut logger = lock(&LOGGER);
+	if level > logger.get_level(target) {
+		return;
+	}
+	let record = Record {
+		level,
+		target: String::from(target),
+		message: fmt::format(args),
+	};
+	for sink in logger.get_sinks().iter_mut() {
+		sink.write(&record);
+	}
+}
+
+/// Writes out anything the sinks buffer.
+pub fn flush() {
+	for sink in lock(&LOGGER).get_sinks().iter_mut() {
+		sink.flush();
+	}
+}
+
+/// Logs a message with the given level, using the current module as the target.
+#[macro_export]
+macro_rules! frame_log {
+	($level:expr, $($arg:tt)+) => {
+		$crate::log::log($level, module_path!(), format_args!($($arg)+))
+	};
+}
+
+#[macro_export]
+macro_rules! frame_error {
+	($($arg:tt)+) => {
+		$crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)+))
+	};
+}
+
+#[macro_export]
+macro_rules! frame_warn {
+	($($arg:tt)+) => {
+		$crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)+))
+	};
+}
+
+#[macro_export]
+macro_rules! frame_info {
+	($($arg:tt)+) => {
+		$crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)+))
+	};
+}
+
+#[macro_export]
+macro_rules! frame_debug {
+	($($arg:tt)+) => {
+		$crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)+))
+	};
+}
+
+#[macro_export]
+macro_rules! frame_trace {
+	($($arg:tt)+) => {
+		$crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)+))
+	};
+}
@@ -0,0 +1,121 @@
+use std::time::Instant;
+
+use super::action::{Action, ActionKind, MenuSubAction};
+use super::clock::GameTime;
+use super::recorder::encode_action;
+
+/// Sees every action the dispatcher is about to hand to the stores, before any store does.
+///
+/// Middleware is chained in the order it was added to the dispatcher. Each one gets the actions
+/// the one before it let through.
+pub trait Middleware {
+	/// Returns the actions to dispatch in place of `action`: nothing to drop it, the action itself
+	/// to let it through, or any number of other actions. Returned actions are dispatched right
+	/// away, in order, without going through the queues or this middleware again.
+	fn process(&mut self, action: Action, time: &GameTime) -> Vec<Action>;
+}
+
+/// Logs the actions going through the dispatcher at `Level::Info`, for debugging.
+pub struct LogMiddleware {
+	kinds: Option<Vec<ActionKind>>,
+}
+
+impl LogMiddleware {
+	/// Logs every action.
+	pub fn new() -> Self {
+		Self {
+			kinds: None,
+		}
+	}
+
+	/// Only logs actions of the given kinds.
+	pub fn for_kinds(kinds: Vec<ActionKind>) -> Self {
+		Self {
+			kinds: Some(kinds),
+		}
+	}
+}
+
+impl Middleware for LogMiddleware {
+	fn process(&mut self, action: Action, time: &GameTime) -> Vec<Action> {
+		let wanted = match self.kinds {
+			Some(ref kinds) => kinds.contains(&action.kind()),
+			None => true,
+		};
+		if wanted {
+			frame_info!("[frame {}] {}", time.frame, encode_action(&action));
+		}
+		return vec![action];
+	}
+}
+
+/// Drops every action the predicate returns `false` for. The predicate can look at shared state,
+/// e.g. to drop `KeyboardAction`s while a text box has focus.
+pub struct FilterMiddleware {
+	keep: Box<FnMut(&Action) -> bool>,
+}
+
+impl FilterMiddleware {
+	pub fn new(keep: Box<FnMut(&Action) -> bool>) -> Self {
+		Self {
+			keep,
+		}
+	}
+}
+
+impl Middleware for FilterMiddleware {
+	fn process(&mut self, action: Action, _time: &GameTime) -> Vec<Action> {
+		if (self.keep)(&action) {
+			return vec![action];
+		}
+		return vec![];
+	}
+}
+
+/// Drops actions that come in again too soon after the last one that was let through. Which
+/// actions count is decided by a predicate.
+///
+/// The window is measured in real time, so it works the same in menus, where game time stands
+/// still. The dispatcher records which player input was dropped and a replay drops the same
+/// input, but other actions are debounced anew, so only match player input in games that get
+/// replayed.
+pub struct DebounceMiddleware {
+	window: f64,
+	matches: Box<Fn(&Action) -> bool>,
+	last: Option<Instant>,
+}
+
+impl DebounceMiddleware {
+	/// `window` is in seconds of real time.
+	pub fn new(window: f64, matches: Box<Fn(&Action) -> bool>) -> Self {
+		Self {
+			window,
+			matches,
+			last: None,
+		}
+	}
+
+	/// Debounces mouse clicks in menus, e.g. so a double click doesn't also press the button that
+	/// shows up under the cursor after the first one.
+	pub fn clicks(window: f64) -> Self {
+		Self::new(window, Box::new(|action| match action {
+			Action::MenuAction(MenuSubAction::ClickAction(..)) => true,
+			_ => false,
+		}))
+	}
+}
+
+impl Middleware for DebounceMiddleware {
+	fn process(&mut self, action: Action, _time: &GameTime) -> Vec<Action> {
+		if !(self.matches)(&action) {
+			return vec![action];
+		}
+		let now = Instant::now();
+		match self.last {
+			Some(last) if now.duration_since(last).as_secs_f64() < self.window => return vec![],
+			_ => {}
+		}
+		self.last = Some(now);
+		return vec![action];
+	}
+}
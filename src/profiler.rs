@@ -0,0 +1,231 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::action::ActionKind;
+use super::store::StoreId;
+
+/// One call of a store's `receive_action`.
+#[derive(Clone, Debug)]
+pub struct StoreSample {
+	pub store: StoreId,
+	pub kind: ActionKind,
+	pub frame: u64,
+	/// When the call started, counted from the creation of the profiler.
+	pub start: Duration,
+	pub duration: Duration,
+}
+
+/// All calls of one store with one kind of action during a frame.
+#[derive(Clone, PartialEq, Debug)]
+pub struct StoreStat {
+	pub store: StoreId,
+	pub kind: ActionKind,
+	pub calls: u32,
+	pub total: Duration,
+}
+
+/// What happened during a single frame.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FrameProfile {
+	pub frame: u64,
+	/// When the frame started, counted from the creation of the profiler.
+	pub start: Duration,
+	/// Time spent in the stores, grouped by store and action kind.
+	pub stores: Vec<StoreStat>,
+	/// How many actions were taken from the secondary queue.
+	pub secondary_drained: usize,
+	/// How many actions were still waiting in the secondary queue when the frame ended.
+	pub secondary_left: usize,
+	/// True if the frame was ended because it ran out of time.
+	pub forced_end: bool,
+}
+
+impl FrameProfile {
+	fn new(frame: u64, start: Duration) -> Self {
+		Self {
+			frame,
+			start,
+			stores: vec![],
+			secondary_drained: 0,
+			secondary_left: 0,
+			forced_end: false,
+		}
+	}
+
+	/// Returns the time spent in all stores together.
+	pub fn get_total(&self) -> Duration {
+		let mut total = Duration::new(0, 0);
+		for stat in self.stores.iter() {
+			total += stat.total;
+		}
+		return total;
+	}
+}
+
+/// How many complete frames a profiler keeps by default.
+pub const DEFAULT_MAX_FRAMES: usize = 600;
+
+/// Measures how long the stores take to handle their actions. Filled by the dispatcher while
+/// profiling is enabled.
+///
+/// Only the last few frames are kept (`DEFAULT_MAX_FRAMES` unless set otherwise), together with
+/// the samples taken during them, so profiling a long session doesn't eat up memory.
+pub struct Profiler {
+	start: Instant,
+	samples: VecDeque<StoreSample>,
+	frames: VecDeque<FrameProfile>,
+	current: FrameProfile,
+	max_frames: usize,
+}
+
+impl Profiler {
+	pub fn new() -> Self {
+		Self {
+			start: Instant::now(),
+			samples: VecDeque::new(),
+			frames: VecDeque::new(),
+			current: FrameProfile::new(0, Duration::new(0, 0)),
+			max_frames: DEFAULT_MAX_FRAMES,
+		}
+	}
+
+	/// Sets how many complete frames are kept. Older ones are dropped, along with their samples.
+	pub fn set_max_frames(&mut self, max_frames: usize) {
+		self.max_frames = max_frames;
+		self.drop_old_frames();
+	}
+
+	pub fn get_max_frames(&self) -> usize {
+		self.max_frames
+	}
+
+	/// Adds a call of `receive_action` that started at `started` and just returned.
+	pub fn record_store(&mut self, store: StoreId, kind: ActionKind, started: Instant) {
+		self.record_store_call(store, kind, started, started.elapsed());
+	}
+
+	/// Adds a call of `receive_action` that started at `started` and took `duration`, e.g. on a
+	/// worker thread.
+	pub fn record_store_call(&mut self, store: StoreId, kind: ActionKind, started: Instant, duration: Duration) {
+		self.samples.push_back(StoreSample {
+			store,
+			kind,
+			frame: self.current.frame,
+			start: started.duration_since(self.start),
+			duration,
+		});
+
+		for stat in self.current.stores.iter_mut() {
+			if stat.store == store && stat.kind == kind {
+				stat.calls += 1;
+				stat.total += duration;
+				return;
+			}
+		}
+		self.current.stores.push(StoreStat {
+			store,
+			kind,
+			calls: 1,
+			total: duration,
+		});
+	}
+
+	/// Counts an action taken from the secondary queue.
+	pub fn record_secondary_drained(&mut self) {
+		self.current.secondary_drained += 1;
+	}
+
+	/// Notes that the current frame ran out of time with `left` actions still in the secondary
+	/// queue.
+	pub fn record_forced_end(&mut self, left: usize) {
+		self.current.forced_end = true;
+		self.current.secondary_left = left;
+	}
+
+	/// Closes the current frame and starts the next one.
+	pub fn end_frame(&mut self, next_frame: u64) {
+		let mut finished = FrameProfile::new(next_frame, self.start.elapsed());
+		::std::mem::swap(&mut finished, &mut self.current);
+		self.frames.push_back(finished);
+		self.drop_old_frames();
+	}
+
+	fn drop_old_frames(&mut self) {
+		while self.frames.len() > self.max_frames {
+			self.frames.pop_front();
+		}
+		let oldest = match self.frames.front() {
+			Some(frame) => frame.frame,
+			None => self.current.frame,
+		};
+		while self.samples.front().map_or(false, |sample| sample.frame < oldest) {
+			self.samples.pop_front();
+		}
+	}
+
+	/// Returns the last complete frame, e.g. for a debug overlay.
+	pub fn get_last_frame(&self) -> Option<&FrameProfile> {
+		self.frames.back()
+	}
+
+	/// Returns the kept complete frames, oldest first.
+	pub fn get_frames(&self) -> &VecDeque<FrameProfile> {
+		&self.frames
+	}
+
+	/// Returns every single store call of the kept frames and the current one.
+	pub fn get_samples(&self) -> &VecDeque<StoreSample> {
+		&self.samples
+	}
+
+	/// Throws away everything recorded so far.
+	pub fn clear(&mut self) {
+		self.samples.clear();
+		self.frames.clear();
+	}
+
+	/// Writes the samples in the Chrome trace event format, which can be opened in
+	/// `about:tracing`. Every store gets its own row.
+	pub fn write_chrome_trace<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		write!(out, "{{\"traceEvents\":[")?;
+		let mut first = true;
+		for sample in self.samples.iter() {
+			if !first {
+				write!(out, ",")?;
+			}
+			first = false;
+			write!(
+				out,
+				"{{\"name\":\"{:?}\",\"cat\":\"store\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{},\"args\":{{\"frame\":{}}}}}",
+				sample.kind, sample.store.0, micros(sample.start), micros(sample.duration), sample.frame,
+			)?;
+		}
+		for frame in self.frames.iter() {
+			if !first {
+				write!(out, ",")?;
+			}
+			first = false;
+			write!(
+				out,
+				"{{\"name\":\"secondary queue\",\"ph\":\"C\",\"pid\":1,\"ts\":{},\"args\":{{\"drained\":{},\"left\":{}}}}}",
+				micros(frame.start), frame.secondary_drained, frame.secondary_left,
+			)?;
+		}
+		write!(out, "],\"displayTimeUnit\":\"ms\"}}")
+	}
+
+	/// Writes the Chrome trace to a file.
+	pub fn save_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut out = BufWriter::new(File::create(path)?);
+		self.write_chrome_trace(&mut out)?;
+		out.flush()
+	}
+}
+
+fn micros(duration: Duration) -> f64 {
+	duration.as_secs_f64() * 1_000_000.0
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+
+use super::action::{Action, CameraSubAction, Envelope, MenuSubAction};
+use super::store::{RequestId, StoreId};
+use super::clock::GameTime;
+use super::img::Img;
+
+const HEADER: &str = "U4XREC 1";
+
+/// Writes the action stream of a dispatcher to a file.
+///
+/// The file is plain text, one entry per line:
+/// * `F <frame> <elapsed>`: a frame started, `elapsed` seconds of real time after the last one.
+/// * `I <poll> <action>`: an input source sent player input the `poll`-th time it was called.
+/// * `D <input>`: the middleware dropped the `input`-th player input that went through it.
+/// * `A <frame> <dt> <action>`: an action was dispatched.
+/// * `R <state>`: the state of the dispatcher's random number generator when recording started.
+pub struct Recorder {
+	out: BufWriter<File>,
+}
+
+impl Recorder {
+	/// Creates the file (or truncates it) and writes the header.
+	pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		let mut out = BufWriter::new(File::create(path)?);
+		writeln!(out, "{}", HEADER)?;
+		Ok(Self {
+			out,
+		})
+	}
+
+	pub fn record_rng(&mut self, state: u64) {
+		self.write_line(format!("R {}", state));
+	}
+
+	pub fn record_frame(&mut self, frame: u64, elapsed: f64) {
+		self.write_line(format!("F {} {}", frame, elapsed));
+	}
+
+	pub fn record_input(&mut self, poll: u64, action: &Action) {
+		self.write_line(format!("I {} {}", poll, encode_action(action)));
+	}
+
+	pub fn record_dropped_input(&mut self, input: u64) {
+		self.write_line(format!("D {}", input));
+	}
+
+	pub fn record_action(&mut self, time: &GameTime, action: &Action) {
+		self.write_line(action_line(time, action));
+	}
+
+	/// Writes everything that is still buffered to the file.
+	pub fn flush(&mut self) {
+		match self.out.flush() {
+			Ok(_) => {}
+			Err(e) => frame_error!("Could not write recording: {}", e),
+		}
+	}
+
+	fn write_line(&mut self, line: String) {
+		match writeln!(self.out, "{}", line) {
+			Ok(_) => {}
+			Err(e) => frame_error!("Could not write recording: {}", e),
+		}
+	}
+}
+
+/// The first point where a replayed run did something else than the recorded one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Divergence {
+	/// Index of the dispatched action, counted from the start of the replay.
+	pub index: usize,
+	/// The recorded line, `None` if the recording had already ended.
+	pub expected: Option<String>,
+	/// What the replayed run dispatched instead.
+	pub found: String,
+}
+
+/// A recording read back from a file. Hands the recorded frame times and player input to the
+/// dispatcher and compares the actions it dispatches with the recorded ones.
+pub struct Replay {
+	frames: HashMap<u64, f64>,
+	inputs: HashMap<u64, Vec<Action>>,
+	dropped_inputs: HashSet<u64>,
+	actions: VecDeque<String>,
+	rng_state: Option<u64>,
+	checked: usize,
+	divergence: Option<Divergence>,
+}
+
+impl Replay {
+	/// Reads a recording written by a `Recorder`.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		let mut frames = HashMap::new();
+		let mut inputs: HashMap<u64, Vec<Action>> = HashMap::new();
+		let mut dropped_inputs = HashSet::new();
+		let mut actions = VecDeque::new();
+		let mut rng_state = None;
+
+		let mut lines = BufReader::new(File::open(path)?).lines();
+		match lines.next() {
+			Some(Ok(ref header)) if header == HEADER => {}
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a recording")),
+		}
+
+		for line in lines {
+			let line = line?;
+			let mut parts = line.splitn(3, ' ');
+			match (parts.next(), parts.next(), parts.next()) {
+				(Some("F"), Some(frame), Some(elapsed)) => {
+					match (frame.parse(), elapsed.parse()) {
+						(Ok(frame), Ok(elapsed)) => {
+							frames.insert(frame, elapsed);
+						}
+						_ => return Err(invalid_line(&line)),
+					}
+				}
+				(Some("I"), Some(poll), Some(action)) => {
+					match (poll.parse(), decode_input(action)) {
+						(Ok(poll), Some(action)) => {
+							inputs.entry(poll).or_insert(vec![]).push(action);
+						}
+						_ => return Err(invalid_line(&line)),
+					}
+				}
+				(Some("D"), Some(input), None) => {
+					match input.parse() {
+						Ok(input) => {
+							dropped_inputs.insert(input);
+						}
+						_ => return Err(invalid_line(&line)),
+					}
+				}
+				(Some("A"), Some(_), Some(_)) => {
+					actions.push_back(line.clone());
+				}
+				(Some("R"), Some(state), None) => {
+					match state.parse() {
+						Ok(state) => rng_state = Some(state),
+						_ => return Err(invalid_line(&line)),
+					}
+				}
+				_ => return Err(invalid_line(&line)),
+			}
+		}
+
+		Ok(Self {
+			frames,
+			inputs,
+			dropped_inputs,
+			actions,
+			rng_state,
+			checked: 0,
+			divergence: None,
+		})
+	}
+
+	/// Returns the state the random number generator had when recording started.
+	pub fn get_rng_state(&self) -> Option<u64> {
+		self.rng_state
+	}
+
+	/// Returns the real time that passed before the given frame in the recorded run.
+	pub fn get_frame_elapsed(&self, frame: u64) -> Option<f64> {
+		match self.frames.get(&frame) {
+			Some(elapsed) => Some(*elapsed),
+			None => None,
+		}
+	}
+
+	/// Returns the player input recorded for the `poll`-th call of an input source.
+	pub fn take_inputs(&mut self, poll: u64) -> Vec<Action> {
+		match self.inputs.remove(&poll) {
+			Some(actions) => actions,
+			None => vec![],
+		}
+	}
+
+	/// Tells if the middleware dropped the `input`-th player input that went through it in the
+	/// recorded run.
+	pub fn was_input_dropped(&mut self, input: u64) -> bool {
+		self.dropped_inputs.remove(&input)
+	}
+
+	/// Compares a dispatched action with the next recorded one. Only the first divergence is
+	/// kept.
+	pub fn check_action(&mut self, time: &GameTime, action: &Action) {
+		let found = action_line(time, action);
+		let expected = self.actions.pop_front();
+		if self.divergence.is_none() && expected.as_ref() != Some(&found) {
+			self.divergence = Some(Divergence {
+				index: self.checked,
+				expected,
+				found,
+			});
+		}
+		self.checked += 1;
+	}
+
+	/// True once every recorded action has been dispatched again.
+	pub fn is_finished(&self) -> bool {
+		self.actions.is_empty()
+	}
+
+	/// Returns where the replay first did something else than the recording, if it did.
+	pub fn get_divergence(&self) -> Option<&Divergence> {
+		self.divergence.as_ref()
+	}
+}
+
+/// Tells if an action is player input, i.e. something an input source (the renderer) only sends
+/// because of a keyboard, mouse or window event.
+pub fn is_input(action: &Action) -> bool {
+	match action {
+		Action::KeyboardAction(_) => true,
+		Action::QuitAction => true,
+		Action::MenuAction(MenuSubAction::ChangeMenuStateAction) => true,
+		Action::MenuAction(MenuSubAction::ClickAction(..)) => true,
+		Action::MenuAction(MenuSubAction::QuitAction) => true,
+		_ => false,
+	}
+}
+
+/// Makes a copy of a player input, `None` for other actions.
+pub(crate) fn copy_input(action: &Action) -> Option<Action> {
+	match action {
+		Action::KeyboardAction(scancode) => Some(Action::KeyboardAction(*scancode)),
+		Action::QuitAction => Some(Action::QuitAction),
+		Action::MenuAction(MenuSubAction::ChangeMenuStateAction) => {
+			Some(Action::MenuAction(MenuSubAction::ChangeMenuStateAction))
+		}
+		Action::MenuAction(MenuSubAction::ClickAction(x, y, button, pixel_size)) => {
+			Some(Action::MenuAction(MenuSubAction::ClickAction(*x, *y, *button, *pixel_size)))
+		}
+		Action::MenuAction(MenuSubAction::QuitAction) => Some(Action::MenuAction(MenuSubAction::QuitAction)),
+		_ => None,
+	}
+}
+
+/// Turns an action into a single line of text. Images are only written as their width and a
+/// hash and custom actions as their description, which is enough to compare two runs but can't be
+/// read back.
+pub fn encode_action(action: &Action) -> String {
+	match action {
+		Action::AddImgToCanvasAction(x, y, depth, img) => {
+			format!("AddImgToCanvasAction {} {} {:?} {} {}", x, y, depth.layer, depth.z, encode_img(img))
+		}
+		Action::DrawAction(clear) => format!("DrawAction {}", clear),
+		Action::UpdateAction => String::from("UpdateAction"),
+		Action::SendFrameAction => String::from("SendFrameAction"),
+		Action::EndFrameAction => String::from("EndFrameAction"),
+		Action::StartAction => String::from("StartAction"),
+		Action::MenuAction(sub) => format!("MenuAction {}", encode_menu_sub_action(sub)),
+		Action::CameraAction(sub) => format!("CameraAction {}", encode_camera_sub_action(sub)),
+		Action::QuitAction => String::from("QuitAction"),
+		Action::EmptyAction => String::from("EmptyAction"),
+		Action::KeyboardAction(scancode) => format!("KeyboardAction {}", *scancode as i32),
+		Action::RemoveStoreAction(id) => format!("RemoveStoreAction {}", id.0),
+		Action::CancelTimerAction(id) => format!("CancelTimerAction {} {}", encode_store(id.owner), id.seq),
+		Action::DirectedAction(envelope) => encode_envelope(envelope),
+		Action::GetCanvasSizeAction => String::from("GetCanvasSizeAction"),
+		Action::CanvasSizeAction(w, h, p) => format!("CanvasSizeAction {} {} {}", w, h, p),
+		Action::VsyncAction(vsync) => format!("VsyncAction {}", vsync),
+		Action::TimeScaleAction(time_scale) => format!("TimeScaleAction {}", time_scale),
+		Action::StepFrameAction => String::from("StepFrameAction"),
+		Action::SaveAction(path) => format!("SaveAction {}", path.display()),
+		Action::LoadAction(path) => format!("LoadAction {}", path.display()),
+		Action::AssetReloadAction(path, bytes) => {
+			format!("AssetReloadAction {} {}:{:016x}", path.display(), bytes.len(), hash(bytes))
+		}
+		Action::StoreErrorAction(id, error) => format!("StoreErrorAction {} {:?}", id.0, error),
+		Action::CustomAction(payload) => format!("CustomAction {}", payload.describe()),
+	}
+}
+
+fn encode_menu_sub_action(sub: &MenuSubAction) -> String {
+	match sub {
+		MenuSubAction::ChangeMenuStateAction => String::from("ChangeMenuStateAction"),
+		MenuSubAction::PushMenuAction(scene) => format!("PushMenuAction {}", scene),
+		MenuSubAction::PopMenuAction => String::from("PopMenuAction"),
+		MenuSubAction::WaitForInputAction => String::from("WaitForInputAction"),
+		MenuSubAction::DrawAction => String::from("DrawAction"),
+		MenuSubAction::AddImgToCanvasAction(x, y, img) => {
+			format!("AddImgToCanvasAction {} {} {}", x, y, encode_img(img))
+		}
+		MenuSubAction::ClickAction(x, y, button, pixel_size) => {
+			format!("ClickAction {} {} {} {}", x, y, *button as u8, pixel_size)
+		}
+		MenuSubAction::QuitAction => String::from("QuitAction"),
+	}
+}
+
+fn encode_camera_sub_action(sub: &CameraSubAction) -> String {
+	match sub {
+		CameraSubAction::MoveToAction(x, y) => format!("MoveToAction {} {}", x, y),
+		CameraSubAction::ScrollAction(dx, dy) => format!("ScrollAction {} {}", dx, dy),
+		CameraSubAction::ZoomAction(zoom) => format!("ZoomAction {}", zoom),
+		CameraSubAction::FollowAction(x, y, w, h) => format!("FollowAction {} {} {} {}", x, y, w, h),
+		CameraSubAction::ShakeAction(amplitude, duration) => format!("ShakeAction {} {}", amplitude, duration),
+	}
+}
+
+fn encode_envelope(envelope: &Envelope) -> String {
+	format!(
+		"DirectedAction {} {} {} {} {}",
+		envelope.to.0,
+		encode_store(envelope.from),
+		encode_request(envelope.request),
+		encode_request(envelope.reply_to),
+		encode_action(&envelope.action),
+	)
+}
+
+fn encode_store(id: Option<StoreId>) -> String {
+	match id {
+		Some(id) => format!("{}", id.0),
+		None => String::from("-"),
+	}
+}
+
+fn encode_request(id: Option<RequestId>) -> String {
+	match id {
+		Some(id) => format!("{}:{}", id.from.0, id.seq),
+		None => String::from("-"),
+	}
+}
+
+fn encode_img(img: &Img) -> String {
+	format!("img:{}:{:016x}", img.get_width(), hash(img.get_pixels()))
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+	// FNV-1a
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for byte in bytes.iter() {
+		hash ^= *byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+/// Reads back an action written by `encode_action`. Only works for player input.
+pub fn decode_input(line: &str) -> Option<Action> {
+	let parts: Vec<&str> = line.split(' ').collect();
+	match parts.as_slice() {
+		["KeyboardAction", scancode] => {
+			match scancode.parse() {
+				Ok(n) => match Scancode::from_i32(n) {
+					Some(scancode) => Some(Action::KeyboardAction(scancode)),
+					None => None,
+				},
+				Err(_) => None,
+			}
+		}
+		["QuitAction"] => Some(Action::QuitAction),
+		["MenuAction", "ChangeMenuStateAction"] => {
+			Some(Action::MenuAction(MenuSubAction::ChangeMenuStateAction))
+		}
+		["MenuAction", "QuitAction"] => Some(Action::MenuAction(MenuSubAction::QuitAction)),
+		["MenuAction", "ClickAction", x, y, button, pixel_size] => {
+			match (x.parse(), y.parse(), button.parse(), pixel_size.parse()) {
+				(Ok(x), Ok(y), Ok(button), Ok(pixel_size)) => {
+					Some(Action::MenuAction(MenuSubAction::ClickAction(
+						x, y, MouseButton::from_ll(button), pixel_size,
+					)))
+				}
+				_ => None,
+			}
+		}
+		_ => None,
+	}
+}
+
+fn action_line(time: &GameTime, action: &Action) -> String {
+	format!("A {} {} {}", time.frame, time.dt, encode_action(action))
+}
+
+fn invalid_line(line: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, format!("invalid line in recording: {}", line))
+}
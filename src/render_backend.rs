@@ -0,0 +1,317 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, Texture};
+use sdl2::sys::SDL_RendererFlags;
+use sdl2::video::Window;
+
+use super::img::Img;
+use super::texture_cache::TextureCache;
+
+/// The color space index that is always transparent.
+const ALPHA_INDEX: u8 = 15;
+
+/// How many frames a backend keeps an image that isn't drawn anymore.
+const MAX_UNUSED_FRAMES: u64 = 60;
+
+/// The drawing half of the renderer. Everything the renderer paints goes through one of these, so
+/// it doesn't need to know whether there is a real window behind it.
+pub trait RenderBackend {
+	/// Fills a rectangle with a color. `index` is the color space index the color was taken
+	/// from (15 being alpha).
+	fn fill_rect(&mut self, rect: Rect, index: u8, color: Color) -> Result<(), String>;
+
+	/// Draws an image with its top left corner at `x`, `y`, every pixel of it covering `size` by
+	/// `size` pixels. Transparent pixels are skipped. Images with the same `id` are the same, so
+	/// backends can keep what they made of one (e.g. a texture) instead of going over its pixels
+	/// again every frame. By default every pixel is filled on its own.
+	fn draw_img(&mut self, _id: u64, img: &Img, colors: &[Color; 15], x: i32, y: i32, size: u32) -> Result<(), String> {
+		draw_pixels(self, img, colors, x, y, size)
+	}
+
+	/// Forgets every image kept by `draw_img`, e.g. because the color space changed.
+	fn clear_textures(&mut self) {}
+
+	/// Shows everything that was drawn since the last call.
+	fn present(&mut self);
+
+	/// Fills the whole drawing area with a color.
+	fn clear(&mut self, color: Color);
+
+	/// Returns the size of the drawing area in pixels.
+	fn output_size(&self) -> Result<(u32, u32), String>;
+
+	/// Whether `present` waits for the vertical sync of the display, which already limits the
+	/// frame rate.
+	fn is_vsync(&self) -> bool {
+		false
+	}
+}
+
+/// Fills every pixel of an image on its own, for backends and images that can't be kept.
+pub fn draw_pixels<B: RenderBackend + ?Sized>(backend: &mut B, img: &Img, colors: &[Color; 15], x: i32, y: i32, size: u32) -> Result<(), String> {
+	for (y_img, row) in img.get_index_rows().iter().enumerate() {
+		for (x_img, &index) in row.iter().enumerate() {
+			if index == ALPHA_INDEX {
+				continue;
+			}
+			let drawing_rect = Rect::new(x + (x_img as u32 * size) as i32, y + (y_img as u32 * size) as i32, size, size);
+			match backend.fill_rect(drawing_rect, index, colors[index as usize]) {
+				Ok(_) => {},
+				Err(e) => return Err(format!("Could not fill shape: {}", e)),
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Draws onto an SDL2 window canvas.
+///
+/// Images drawn with `draw_img` are uploaded into a texture the first time and copied from there
+/// afterwards. The textures belong to the canvas: evicted ones are destroyed right away, the rest
+/// go with the SDL renderer when the backend is dropped.
+pub struct CanvasBackend {
+	canvas: Canvas<Window>,
+	// None for images without pixels, which SDL can't make a texture of.
+	textures: TextureCache<Option<Texture>>,
+}
+
+impl CanvasBackend {
+	pub fn new(canvas: Canvas<Window>) -> Self {
+		Self {
+			canvas,
+			textures: TextureCache::new(MAX_UNUSED_FRAMES),
+		}
+	}
+
+	/// Frees textures that were dropped from the cache.
+	fn destroy_textures(textures: Vec<Option<Texture>>) {
+		for texture in textures.into_iter().filter_map(|texture| texture) {
+			// The canvas, and with it the SDL renderer the texture was made by, is still alive.
+			unsafe { texture.destroy(); }
+		}
+	}
+}
+
+fn upload_texture(canvas: &Canvas<Window>, img: &Img, colors: &[Color; 15]) -> Result<Option<Texture>, String> {
+	let rows = img.get_index_rows();
+	let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+	let height = rows.len();
+	if width == 0 || height == 0 {
+		return Ok(None);
+	}
+
+	let mut rgba = vec![0; width * height * 4];
+	for (y, row) in rows.iter().enumerate() {
+		for (x, &index) in row.iter().enumerate() {
+			if index == ALPHA_INDEX {
+				continue;
+			}
+			let color = colors[index as usize];
+			let o = (y * width + x) * 4;
+			rgba[o..o + 4].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+		}
+	}
+
+	let mut texture = canvas.create_texture_static(PixelFormatEnum::RGBA32, width as u32, height as u32)
+		.map_err(|e| e.to_string())?;
+	texture.update(None, &rgba, width * 4).map_err(|e| e.to_string())?;
+	texture.set_blend_mode(BlendMode::Blend);
+	Ok(Some(texture))
+}
+
+impl RenderBackend for CanvasBackend {
+	fn fill_rect(&mut self, rect: Rect, _index: u8, color: Color) -> Result<(), String> {
+		self.canvas.set_draw_color(color);
+		self.canvas.fill_rect(rect)?;
+		self.canvas.draw_rect(rect)
+	}
+
+	fn draw_img(&mut self, id: u64, img: &Img, colors: &[Color; 15], x: i32, y: i32, size: u32) -> Result<(), String> {
+		let canvas = &self.canvas;
+		let texture = match *self.textures.get_or_upload(id, || upload_texture(canvas, img, colors))? {
+			Some(ref texture) => texture,
+			None => return Ok(()),
+		};
+		let query = texture.query();
+		self.canvas.copy(texture, None, Rect::new(x, y, query.width * size, query.height * size))
+	}
+
+	fn clear_textures(&mut self) {
+		Self::destroy_textures(self.textures.clear());
+	}
+
+	fn present(&mut self) {
+		self.canvas.present();
+		Self::destroy_textures(self.textures.end_frame());
+	}
+
+	fn clear(&mut self, color: Color) {
+		self.canvas.set_draw_color(color);
+		self.canvas.clear();
+	}
+
+	fn output_size(&self) -> Result<(u32, u32), String> {
+		self.canvas.output_size()
+	}
+
+	fn is_vsync(&self) -> bool {
+		self.canvas.info().flags & SDL_RendererFlags::SDL_RENDERER_PRESENTVSYNC as u32 != 0
+	}
+}
+
+/// An offscreen framebuffer that keeps every pixel in memory, both as the color space index it was
+/// drawn with and as its RGBA value. Needs neither a window nor a display, so it's what the tests
+/// render into.
+///
+/// Drawing happens on a back buffer, `present` copies it to the front buffer. All getters read the
+/// front buffer, i.e. the last presented frame.
+///
+/// Like `CanvasBackend`, it keeps images drawn with `draw_img`, already turned into colors, so
+/// tests can see when images are uploaded.
+pub struct FrameBuffer {
+	width: u32,
+	height: u32,
+	back_indices: Vec<Option<u8>>,
+	back_colors: Vec<Color>,
+	front_indices: Vec<Option<u8>>,
+	front_colors: Vec<Color>,
+	frames_presented: u64,
+	textures: TextureCache<Vec<Vec<Option<(u8, Color)>>>>,
+}
+
+impl FrameBuffer {
+	/// Creates a black framebuffer of the given size in pixels.
+	pub fn new(width: u32, height: u32) -> Self {
+		let len = (width * height) as usize;
+		Self {
+			width,
+			height,
+			back_indices: vec![None; len],
+			back_colors: vec![Color::RGB(0, 0, 0); len],
+			front_indices: vec![None; len],
+			front_colors: vec![Color::RGB(0, 0, 0); len],
+			frames_presented: 0,
+			textures: TextureCache::new(MAX_UNUSED_FRAMES),
+		}
+	}
+
+	/// Returns the color space index of a presented pixel. `None` if the pixel was only cleared or
+	/// lies outside of the buffer.
+	pub fn get_index(&self, x: u32, y: u32) -> Option<u8> {
+		match self.offset(x, y) {
+			Some(o) => self.front_indices[o],
+			None => None,
+		}
+	}
+
+	/// Returns the color of a presented pixel.
+	pub fn get_color(&self, x: u32, y: u32) -> Option<Color> {
+		match self.offset(x, y) {
+			Some(o) => Some(self.front_colors[o]),
+			None => None,
+		}
+	}
+
+	/// Returns the presented frame as tightly packed RGBA bytes, row by row.
+	pub fn get_rgba(&self) -> Vec<u8> {
+		let mut out_vec = Vec::with_capacity(self.front_colors.len() * 4);
+		for color in self.front_colors.iter() {
+			out_vec.push(color.r);
+			out_vec.push(color.g);
+			out_vec.push(color.b);
+			out_vec.push(color.a);
+		}
+		return out_vec;
+	}
+
+	/// Returns how often `present` has been called.
+	pub fn get_frames_presented(&self) -> u64 {
+		self.frames_presented
+	}
+
+	/// Returns the number of images kept from `draw_img`.
+	pub fn get_texture_count(&self) -> usize {
+		self.textures.len()
+	}
+
+	/// Returns how many times an image was turned into colors for `draw_img`, over all frames.
+	pub fn get_texture_uploads(&self) -> u64 {
+		self.textures.get_uploads()
+	}
+
+	fn offset(&self, x: u32, y: u32) -> Option<usize> {
+		if x < self.width && y < self.height {
+			Some((y * self.width + x) as usize)
+		} else {
+			None
+		}
+	}
+}
+
+impl RenderBackend for FrameBuffer {
+	fn fill_rect(&mut self, rect: Rect, index: u8, color: Color) -> Result<(), String> {
+		fill_back_buffer(self.width, self.height, &mut self.back_indices, &mut self.back_colors, rect, index, color);
+		Ok(())
+	}
+
+	fn draw_img(&mut self, id: u64, img: &Img, colors: &[Color; 15], x: i32, y: i32, size: u32) -> Result<(), String> {
+		let rows = self.textures.get_or_upload(id, || {
+			Ok(img.get_index_rows().iter().map(|row| {
+				row.iter().map(|&index| if index == ALPHA_INDEX { None } else { Some((index, colors[index as usize])) }).collect()
+			}).collect())
+		})?;
+		for (y_img, row) in rows.iter().enumerate() {
+			for (x_img, pixel) in row.iter().enumerate() {
+				let (index, color) = match *pixel {
+					Some(pixel) => pixel,
+					None => continue,
+				};
+				let rect = Rect::new(x + (x_img as u32 * size) as i32, y + (y_img as u32 * size) as i32, size, size);
+				fill_back_buffer(self.width, self.height, &mut self.back_indices, &mut self.back_colors, rect, index, color);
+			}
+		}
+		Ok(())
+	}
+
+	fn clear_textures(&mut self) {
+		self.textures.clear();
+	}
+
+	fn present(&mut self) {
+		self.front_indices.copy_from_slice(&self.back_indices);
+		self.front_colors.copy_from_slice(&self.back_colors);
+		self.frames_presented += 1;
+		self.textures.end_frame();
+	}
+
+	fn clear(&mut self, color: Color) {
+		for index in self.back_indices.iter_mut() {
+			*index = None;
+		}
+		for c in self.back_colors.iter_mut() {
+			*c = color;
+		}
+	}
+
+	fn output_size(&self) -> Result<(u32, u32), String> {
+		Ok((self.width, self.height))
+	}
+}
+
+/// Fills a rect of the back buffer of a `FrameBuffer`. Takes the fields one by one, so it can be
+/// called while the texture cache is borrowed.
+fn fill_back_buffer(width: u32, height: u32, indices: &mut [Option<u8>], colors: &mut [Color], rect: Rect, index: u8, color: Color) {
+	// Clip the rect to the buffer, like SDL does.
+	let x_start = rect.x().max(0) as u32;
+	let y_start = rect.y().max(0) as u32;
+	let x_end = (rect.x() + rect.width() as i32).max(0) as u32;
+	let y_end = (rect.y() + rect.height() as i32).max(0) as u32;
+
+	for y in y_start..y_end.min(height) {
+		for x in x_start..x_end.min(width) {
+			let o = (y * width + x) as usize;
+			indices[o] = Some(index);
+			colors[o] = color;
+		}
+	}
+}
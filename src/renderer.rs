@@ -8,30 +8,41 @@ use sdl2::event::Event;
 use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
 use sdl2::Sdl;
-use sdl2::video::Window;
 use sdl2::VideoSubsystem;
 
 use action::MenuSubAction;
 
 use super::action::Action;
-use super::img::Img;
-use super::store::ReceiveActionReturnOption;
+use super::action::ActionKind;
+use super::action::{CameraSubAction, Depth, Layer};
+use super::camera::Camera;
+use super::img::{Img, ImgHandle};
+use super::render_backend::{draw_pixels, CanvasBackend, FrameBuffer, RenderBackend};
+use super::store::Context;
 use super::store::Store;
+use super::store::StoreResult;
 
 /// This is where the framework talks to SDL2 and where the color space is stored.
-pub struct Renderer {
-	pub sdl_context: Sdl,
-	event_pump: EventPump,
-	video_subsystem: VideoSubsystem,
-	canvas: Canvas<Window>,
+///
+/// All drawing goes through the backend `B`. `Renderer::new` opens a window, while
+/// `Renderer::new_headless` draws into a `FrameBuffer` and doesn't touch SDL at all.
+///
+/// Images sent during a frame are collected and only painted on the next `DrawAction`, sorted by
+/// their `Depth`, so the order the stores send them in doesn't matter. Images on the background
+/// and world layers are placed in world coordinates and go through the `Camera`.
+pub struct Renderer<B: RenderBackend = CanvasBackend> {
+	pub sdl_context: Option<Sdl>,
+	event_pump: Option<EventPump>,
+	video_subsystem: Option<VideoSubsystem>,
+	backend: B,
 	color_space: [Color; 15],
 	pixel_size: u32,
+	draw_commands: Vec<(Depth, i32, i32, ImgHandle)>,
+	camera: Camera,
 }
 
-impl Renderer {
+impl Renderer<CanvasBackend> {
 	pub fn new(width: u32, height: u32, space: Option<[Color; 15]>) -> Self {
 		let sdl_context = sdl2::init().unwrap();
 		let event_pump = match sdl_context.event_pump() {
@@ -44,6 +55,25 @@ impl Renderer {
 			.build()
 			.unwrap();
 		let canvas = window.into_canvas().build().unwrap();
+		let mut renderer = Self::with_backend(CanvasBackend::new(canvas), space);
+		renderer.sdl_context = Some(sdl_context);
+		renderer.event_pump = Some(event_pump);
+		renderer.video_subsystem = Some(video_subsystem);
+		return renderer;
+	}
+}
+
+impl Renderer<FrameBuffer> {
+	/// Creates a renderer that draws into an in-memory framebuffer. There are no inputs without
+	/// a window, so it never sends any input actions.
+	pub fn new_headless(width: u32, height: u32, space: Option<[Color; 15]>) -> Self {
+		Self::with_backend(FrameBuffer::new(width, height), space)
+	}
+}
+
+impl<B: RenderBackend> Renderer<B> {
+	/// Creates a renderer on top of any backend. No SDL context is created.
+	pub fn with_backend(backend: B, space: Option<[Color; 15]>) -> Self {
 		let color_space: [Color; 15];
 		match space {
 			Some(space) => {
@@ -71,27 +101,38 @@ impl Renderer {
 			}
 		}
 		return Self {
-			sdl_context,
-			event_pump,
-			video_subsystem,
-			canvas,
+			sdl_context: None,
+			event_pump: None,
+			video_subsystem: None,
+			backend,
 			color_space,
 			pixel_size: 8,
+			draw_commands: vec![],
+			camera: Camera::new(),
 		}
 	}
 
+	/// Whether presenting a frame waits for the vertical sync of the display.
+	pub fn is_vsync(&self) -> bool {
+		self.backend.is_vsync()
+	}
+
+	/// Returns the backend, e.g. to inspect the pixels of a `FrameBuffer`.
+	pub fn get_backend(&self) -> &B {
+		&self.backend
+	}
+
 	#[test]
 	pub fn test(&mut self, img: Img) -> bool {
-		print!("This is the Test function!\n");
-		self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-		self.canvas.clear();
-		self.canvas.present();
+		frame_debug!("This is the Test function!");
+		self.backend.clear(Color::RGB(0, 0, 0));
+		self.backend.present();
 		let mut i = 0;
 		'running: loop {
 			i += 1;
-			println!("Draw cycle: {}", i);
-			self.add_to_canvas(0, 0, img.clone());
-			self.draw_with_clear();
+			frame_debug!("Draw cycle: {}", i);
+			self.add_to_canvas(0, 0, img.clone()).unwrap();
+			self.draw_with_clear().unwrap();
 			sleep(Duration::new(1, 0));
 			if i > 10 {
 				break 'running;
@@ -101,62 +142,82 @@ impl Renderer {
 		return true;
 	}
 
-	///Adds an image to the canvas in the desired location
-	pub fn add_to_canvas(&mut self, x: u32, y: u32, i: Img) {
-		let mask: u8 = 0b00001111;
-		let alpha_value: u8 = 15;
-
-		for (y_img, row) in i.enumerate() {
-			for (x_img, pixel_pair) in row.iter().enumerate() {
-				for i in 0..2 { //get each pixel of pair
+	///Adds an image to the canvas in the desired location right away, on top of what's there.
+	/// Transparent pixels leave the canvas as it is. Fails if the backend can't draw a pixel.
+	///
+	/// The image is painted pixel by pixel and not kept by the backend; queue an `ImgHandle` for
+	/// anything drawn every frame.
+	pub fn add_to_canvas(&mut self, x: u32, y: u32, i: Img) -> Result<(), String> {
+		let pixel_size = self.pixel_size;
+		draw_pixels(&mut self.backend, &i, &self.color_space, x as i32 * pixel_size as i32, y as i32 * pixel_size as i32, pixel_size)
+	}
 
-					let pixel = (pixel_pair >> (4 * i)) & mask;
+	/// Collects an image to be painted with the next `draw_with_clear` or `draw_without_clear`.
+	/// The backend keeps the image by the id of the handle, so queue the same handle every frame
+	/// instead of a new one.
+	pub fn queue_img(&mut self, x: i32, y: i32, depth: Depth, img: ImgHandle) {
+		self.draw_commands.push((depth, x, y, img));
+	}
 
-					if pixel != alpha_value {
-						let pixel_value = pixel as usize;
-						self.canvas.set_draw_color(self.color_space[pixel_value])
-					} else {
-						self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 0))
-					}
+	/// Paints the collected images from the lowest depth to the highest. Images of the same depth
+	/// keep the order they were collected in. If one of them fails, the rest are dropped.
+	fn paint_queued(&mut self) -> Result<(), String> {
+		let mut draw_commands = vec![];
+		draw_commands.append(&mut self.draw_commands);
+		draw_commands.sort_by_key(|command| command.0);
+		for (depth, x, y, img) in draw_commands {
+			let (x, y, scale) = if depth.layer.is_in_world_space() {
+				let (x, y) = self.camera.world_to_screen(x, y);
+				(x, y, self.camera.get_zoom())
+			} else {
+				(x, y, 1)
+			};
+			let pixel_size = self.pixel_size;
+			self.backend.draw_img(img.get_id(), &img, &self.color_space, x * pixel_size as i32, y * pixel_size as i32, pixel_size * scale)?;
+		}
+		Ok(())
+	}
 
-					/*The following line makes it so the pixel pairs are drawn side by side in the
-					right order*/
-					let x_i32 = (x_img * 2) as i32 + x as i32 + (1 - i);
-					let y_i32 = y_img as i32 + y as i32;
-					let drawing_rect = Rect::new(self.pixel_size as i32 * x_i32, self.pixel_size as i32 * y_i32, self.pixel_size, self.pixel_size);
+	/// Paints the collected images, draws the canvas and starts the next one on a black background
+	pub fn draw_with_clear(&mut self) -> Result<(), String> {
+		let painted = self.paint_queued();
+		self.backend.present();
+		self.backend.clear(Color::RGB(0, 0, 0));
+		painted
+	}
 
-					match self.canvas.fill_rect(drawing_rect) {
-						Ok(_) => {},
-						Err(e) => eprintln!("Could not fill shape:{}", e),
-					}
+	/// Paints the collected images and just draws the canvas
+	pub fn draw_without_clear(&mut self) -> Result<(), String> {
+		let painted = self.paint_queued();
+		self.backend.present();
+		painted
+	}
 
-					match self.canvas.draw_rect(drawing_rect) {
-						Ok(_) => {},
-						Err(e) => eprintln!("Could not draw shape:{}", e),
-					}
-				}
-			}
-		}
+	pub fn get_camera(&self) -> &Camera {
+		&self.camera
 	}
 
-	/// Draws a black background and then the canvas
-	pub fn draw_with_clear(&mut self) {
-		self.canvas.present();
-		self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-		self.canvas.clear();
+	/// Returns the camera, e.g. to move it before the game starts. Stores move it with a
+	/// `CameraAction`.
+	pub fn get_camera_mut(&mut self) -> &mut Camera {
+		&mut self.camera
 	}
 
-	/// Just draws the canvas
-	pub fn draw_without_clear(&mut self) {
-		self.canvas.present();
+	/// Returns the size of the canvas in game pixels.
+	fn get_view_size(&mut self) -> (u32, u32) {
+		let size = self.get_canvas_size();
+		(size.0 / self.pixel_size, size.1 / self.pixel_size)
 	}
 
+	/// Sets the colors of the 15 color space indices. Images the backend kept are uploaded again
+	/// with the new colors.
 	pub fn change_color_space(&mut self, space: [Color; 15]) {
 		self.color_space = space;
+		self.backend.clear_textures();
 	}
 
 	pub fn get_canvas_size(&mut self) -> (u32, u32) {
-		match self.canvas.output_size() {
+		match self.backend.output_size() {
 			Ok(r) => (r.0, r.1),
 			_ => (0, 0)
 		}
@@ -165,7 +226,11 @@ impl Renderer {
 	/// This function is used for all user input (i.e. Mouse, Keyboard...)
 	fn handle_inputs(&mut self) -> Vec<Action> {
 		let mut out_vec: Vec<Action> = vec![];
-		for event in self.event_pump.poll_iter() {
+		let event_pump = match self.event_pump {
+			Some(ref mut pump) => pump,
+			None => return out_vec,
+		};
+		for event in event_pump.poll_iter() {
 			match event {
 				Event::Quit { .. } => {
 					out_vec.push(Action::MenuAction(MenuSubAction::QuitAction));
@@ -189,7 +254,11 @@ impl Renderer {
 	/// Used mainly in menus to decrease memory usage while no new information is given
 	fn wait_for_inputs(&mut self) -> Vec<Action> {
 		let mut out_vec: Vec<Action> = vec![];
-		match self.event_pump.wait_event() {
+		let event_pump = match self.event_pump {
+			Some(ref mut pump) => pump,
+			None => return out_vec,
+		};
+		match event_pump.wait_event() {
 			Event::Quit { .. } => {
 				out_vec.push(Action::QuitAction);
 			}
@@ -208,56 +277,81 @@ impl Renderer {
 	}
 }
 
-impl<'a> Store<'a> for Renderer {
-	fn receive_action(&'a mut self, action: &Action, dt: &f64) -> ReceiveActionReturnOption {
+impl<B: RenderBackend> Store for Renderer<B> {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
 		match action {
-			&Action::AddImgToCanvasAction(ref x, ref y, ref i) => {
-				self.add_to_canvas(*x, *y, i.clone());
-				return ReceiveActionReturnOption::NoNewAction(self);
+			&Action::AddImgToCanvasAction(x, y, depth, ref i) => {
+				self.queue_img(x, y, depth, i.clone());
 			}
 			&Action::DrawAction(clear) => {
 				if clear {
-					self.draw_with_clear();
+					self.draw_with_clear()?;
 				} else {
-					self.draw_without_clear();
+					self.draw_without_clear()?;
 				}
-				return ReceiveActionReturnOption::NoNewAction(self);
+			}
+			&Action::StartAction => {
+				ctx.send(Action::VsyncAction(self.is_vsync()));
 			}
 			&Action::EndFrameAction => {
+				self.camera.update(ctx.get_frame_time());
+				for input in self.handle_inputs() {
+					ctx.send(input);
+				}
+				ctx.send(Action::UpdateAction);
+				ctx.send(Action::SendFrameAction);
+				ctx.send(Action::DrawAction(true));
+			}
+			&Action::CameraAction(sub) => {
+				match sub {
+					CameraSubAction::MoveToAction(x, y) => self.camera.set_pos((x, y)),
+					CameraSubAction::ScrollAction(dx, dy) => self.camera.scroll(dx, dy),
+					CameraSubAction::ZoomAction(zoom) => self.camera.set_zoom(zoom),
+					CameraSubAction::FollowAction(x, y, width, height) => {
+						let (view_width, view_height) = self.get_view_size();
+						self.camera.center_on(x, y, width, height, view_width, view_height);
+					}
+					CameraSubAction::ShakeAction(amplitude, duration) => self.camera.shake(amplitude, duration),
+				}
+			}
+			&Action::GetCanvasSizeAction => {
 				let size = self.get_canvas_size();
-				let mut out_vec = self.handle_inputs();
-				out_vec.push(Action::UpdateAction);
-				out_vec.push(Action::SendFrameAction(size.0, size.1, self.pixel_size));
-				out_vec.push(Action::DrawAction(true));
-				return ReceiveActionReturnOption::NewAction(
-					out_vec,
-					false,
-					self,
-				);
+				ctx.reply(Action::CanvasSizeAction(size.0, size.1, self.pixel_size));
 			}
 			&Action::MenuAction(ref sub) => {
 				match sub {
 					MenuSubAction::WaitForInputAction => {
-						return ReceiveActionReturnOption::NewAction(
-							self.wait_for_inputs(),
-							false,
-							self,
-						);
+						for input in self.wait_for_inputs() {
+							ctx.send(input);
+						}
 					}
 					MenuSubAction::DrawAction => {
-						self.draw_without_clear();
-						return ReceiveActionReturnOption::NoNewAction(self);
+						self.draw_without_clear()?;
 					}
-					MenuSubAction::AddImgToCanvasAction(ref x, ref y, ref i) => {
-						self.add_to_canvas(*x, *y, i.clone());
-						return ReceiveActionReturnOption::NoNewAction(self);
+					MenuSubAction::AddImgToCanvasAction(x, y, ref i) => {
+						self.queue_img(*x as i32, *y as i32, Depth::new(Layer::Menu, 0), i.clone());
 					}
-					_ => ReceiveActionReturnOption::NoNewAction(self)
+					_ => {}
 				}
 			}
-			_ => return ReceiveActionReturnOption::NoNewAction(self)
+			_ => {}
 		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![
+			ActionKind::AddImgToCanvasAction,
+			ActionKind::CameraAction,
+			ActionKind::DrawAction,
+			ActionKind::EndFrameAction,
+			ActionKind::MenuAction,
+			ActionKind::StartAction,
+		])
+	}
+
+	fn is_input_source(&self) -> bool {
+		true
 	}
 }
 
-//todo: render layers?
\ No newline at end of file
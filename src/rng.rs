@@ -0,0 +1,64 @@
+use rand::{Error, RngCore};
+
+/// The random number generator of a dispatcher. Draws the same numbers for the same seed on every
+/// machine, so map generation and combat rolls can be reproduced. Implements `rand::RngCore`, so
+/// everything from `rand::Rng` (like `gen_range`) works with it.
+///
+/// It's a SplitMix64 generator: fast and good enough for games, but not for anything that has to
+/// be unpredictable. Its whole state is a single `u64`, which goes into save files and recordings.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GameRng {
+	state: u64,
+}
+
+impl GameRng {
+	pub fn new(seed: u64) -> Self {
+		Self {
+			state: seed,
+		}
+	}
+
+	/// Returns the state, to pick up the same sequence later with `set_state`.
+	pub fn get_state(&self) -> u64 {
+		self.state
+	}
+
+	pub fn set_state(&mut self, state: u64) {
+		self.state = state;
+	}
+
+	/// Returns a new generator seeded from this one. The dispatcher hands forks to stores that
+	/// receive actions in parallel, so they don't have to share one.
+	pub fn fork(&mut self) -> Self {
+		Self::new(self.next_u64())
+	}
+}
+
+impl RngCore for GameRng {
+	fn next_u32(&mut self) -> u32 {
+		(self.next_u64() >> 32) as u32
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.state;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	fn fill_bytes(&mut self, dest: &mut [u8]) {
+		for chunk in dest.chunks_mut(8) {
+			let mut value = self.next_u64();
+			for byte in chunk.iter_mut() {
+				*byte = value as u8;
+				value >>= 8;
+			}
+		}
+	}
+
+	fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+		self.fill_bytes(dest);
+		Ok(())
+	}
+}
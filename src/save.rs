@@ -0,0 +1,148 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"U4XS";
+const VERSION: u32 = 2;
+
+/// The snapshots of all stores that take part in saving, each under the store's save key, and
+/// the state of the dispatcher's random number generator.
+///
+/// On disk it's the magic bytes `U4XS`, the format version, the random number generator's state
+/// (a flag that tells whether there is one, then its low and high half) and the number of
+/// entries, followed by the entries. Each entry is its key and its data, both prefixed with their
+/// length. All numbers are little endian `u32`s. Files of version 1 have no random number
+/// generator state and can still be read.
+#[derive(Clone, PartialEq, Debug)]
+pub struct SaveFile {
+	entries: Vec<(String, Vec<u8>)>,
+	rng_state: Option<u64>,
+}
+
+impl SaveFile {
+	pub fn new() -> Self {
+		Self {
+			entries: vec![],
+			rng_state: None,
+		}
+	}
+
+	/// Adds the snapshot of a store. A second snapshot under the same key replaces the first.
+	pub fn insert(&mut self, key: String, data: Vec<u8>) {
+		match self.entries.iter().position(|entry| entry.0 == key) {
+			Some(index) => self.entries[index].1 = data,
+			None => self.entries.push((key, data)),
+		}
+	}
+
+	/// Returns the snapshot saved under a key.
+	pub fn get(&self, key: &str) -> Option<&[u8]> {
+		match self.entries.iter().find(|entry| entry.0 == key) {
+			Some(entry) => Some(&entry.1),
+			None => None,
+		}
+	}
+
+	/// Returns the keys of all snapshots, in the order they were saved.
+	pub fn get_keys(&self) -> Vec<&str> {
+		self.entries.iter().map(|entry| entry.0.as_str()).collect()
+	}
+
+	/// Sets the state of the random number generator, see `GameRng::get_state`.
+	pub fn set_rng_state(&mut self, state: u64) {
+		self.rng_state = Some(state);
+	}
+
+	pub fn get_rng_state(&self) -> Option<u64> {
+		self.rng_state
+	}
+
+	pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+		out.write_all(MAGIC)?;
+		write_u32(out, VERSION)?;
+		match self.rng_state {
+			Some(state) => {
+				write_u32(out, 1)?;
+				write_u32(out, state as u32)?;
+				write_u32(out, (state >> 32) as u32)?;
+			}
+			None => write_u32(out, 0)?,
+		}
+		write_u32(out, self.entries.len() as u32)?;
+		for &(ref key, ref data) in self.entries.iter() {
+			write_bytes(out, key.as_bytes())?;
+			write_bytes(out, data)?;
+		}
+		Ok(())
+	}
+
+	pub fn read<R: Read>(input: &mut R) -> io::Result<Self> {
+		let mut magic = [0u8; 4];
+		input.read_exact(&mut magic)?;
+		if &magic != MAGIC {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "not a save file"));
+		}
+		let version = read_u32(input)?;
+		if version == 0 || version > VERSION {
+			return Err(io::Error::new(
+				io::ErrorKind::InvalidData,
+				format!("unsupported save file version {}", version),
+			));
+		}
+
+		let mut save = Self::new();
+		if version >= 2 && read_u32(input)? != 0 {
+			let low = read_u32(input)? as u64;
+			let high = read_u32(input)? as u64;
+			save.set_rng_state(low | high << 32);
+		}
+		for _ in 0..read_u32(input)? {
+			let key = match String::from_utf8(read_bytes(input)?) {
+				Ok(key) => key,
+				Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "save key isn't UTF-8")),
+			};
+			let data = read_bytes(input)?;
+			save.insert(key, data);
+		}
+		Ok(save)
+	}
+
+	/// Writes the save to a file, replacing whatever was there.
+	pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+		let mut out = BufWriter::new(File::create(path)?);
+		self.write(&mut out)?;
+		out.flush()
+	}
+
+	/// Reads a save written by `save`.
+	pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+		Self::read(&mut BufReader::new(File::open(path)?))
+	}
+}
+
+fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+	out.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+	write_u32(out, bytes.len() as u32)?;
+	out.write_all(bytes)
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+	let mut bytes = [0u8; 4];
+	input.read_exact(&mut bytes)?;
+	Ok(bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24)
+}
+
+/// Only reads what's really there, so a broken length can't make it allocate gigabytes.
+fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+	let len = read_u32(input)? as u64;
+	let mut bytes = vec![];
+	input.by_ref().take(len).read_to_end(&mut bytes)?;
+	if bytes.len() as u64 != len {
+		return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "save file ends in the middle of an entry"));
+	}
+	Ok(bytes)
+}
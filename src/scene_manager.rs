@@ -1,12 +1,19 @@
+use std::path::Path;
+
 use sdl2::mouse::MouseButton;
 
-use action::{Action, MenuSubAction};
-use store;
+use action::{Action, ActionKind, MenuSubAction};
+use store::{Context, StoreResult};
 
 
-pub trait Scene<'a> {
+pub trait Scene {
 	//elements: vec<frame::game_obj::GameObj>
-	fn receive_menu_sub_action(&mut self, sub_action: &super::action::MenuSubAction) -> super::store::ReceiveMSAReturnOption;
+	/// Handles a menu sub action and returns the sub actions it leads to.
+	fn receive_menu_sub_action(&mut self, sub_action: &super::action::MenuSubAction) -> Vec<MenuSubAction>;
+
+	/// Called for every `AssetReloadAction`, whether the scene is open or not. Scenes with sprites
+	/// pass it on to `Sprite::reload_asset`. Does nothing by default.
+	fn reload_asset(&mut self, _path: &Path, _buf: &[u8]) {}
 }
 
 pub struct MinimalScene {
@@ -21,127 +28,127 @@ impl MinimalScene {
 	}
 }
 
-impl<'a> Scene<'a> for MinimalScene {
-	fn receive_menu_sub_action(&mut self, sub_action: &super::action::MenuSubAction) -> super::store::ReceiveMSAReturnOption {
+impl Scene for MinimalScene {
+	fn receive_menu_sub_action(&mut self, sub_action: &super::action::MenuSubAction) -> Vec<MenuSubAction> {
 		match sub_action {
 			super::action::MenuSubAction::DrawAction => {
-				return super::store::ReceiveMSAReturnOption::NewAction(
-					vec!(
-						self.button.send_frame(),
-					),
-					self,
+				return vec!(
+					self.button.send_frame(),
 				);
 			}
 
 			super::action::MenuSubAction::ClickAction(x, y, mouse_btn, pix_size) => {
 				match mouse_btn {
 					MouseButton::Left => match self.button.check_click(*x as u32, *y as u32, pix_size.clone()) {
-						Some(msa) => return super::store::ReceiveMSAReturnOption::NewAction(vec![msa], self),
-						None => return super::store::ReceiveMSAReturnOption::NoNewAction(self)
+						Some(msa) => return vec![msa],
+						None => return vec![]
 					},
-					_ => return super::store::ReceiveMSAReturnOption::NoNewAction(self)
+					_ => return vec![]
 				}
 			}
 
 			_ => {
-				return super::store::ReceiveMSAReturnOption::NoNewAction(self);
+				return vec![];
 			}
 		}
 	}
+
+	fn reload_asset(&mut self, path: &Path, buf: &[u8]) {
+		self.button.reload_asset(path, buf);
+	}
 }
 
-/// Very similar to the dispatcher, the SceneManager supervises menus.
-pub struct SceneManager<'a> {
-	scenes: Option<Vec<Option<&'a mut Scene<'a>>>>,
-	// Works exactly lke the dispatchers store refs.
-	current_scene: usize,
+/// Very similar to the dispatcher, the SceneManager supervises menus. Menus can be opened on top
+/// of each other, only the topmost one gets to handle input and draw.
+pub struct SceneManager {
+	scenes: Vec<Box<Scene>>,
+	open_scenes: Vec<usize>,
 }
 
-impl<'a> SceneManager<'a> {
+impl SceneManager {
 	pub fn new() -> Self {
 		Self {
-			scenes: None,
-			current_scene: 0,
+			scenes: vec![],
+			open_scenes: vec![],
 		}
 	}
 
-	pub fn add_scenes(&mut self, references: Vec<&'a mut Scene<'a>>) {
-		let mut local_scenes: Vec<Option<&'a mut Scene<'a>>>;
-
-		match self.scenes.take() {
-			Some(x) => {
-				local_scenes = x;
-			}
-			_ => {
-				local_scenes = vec![];
-			}
-		}
+	/// Returns the index of the scene of the topmost menu, `None` if no menu is open.
+	pub fn get_current_scene(&self) -> Option<usize> {
+		self.open_scenes.last().cloned()
+	}
 
-		for reference in references {
-			local_scenes.push(Some(reference));
+	pub fn add_scenes(&mut self, scenes: Vec<Box<Scene>>) {
+		for scene in scenes {
+			self.scenes.push(scene);
 		}
-
-		self.scenes = Some(local_scenes);
 	}
 }
 
-impl<'a> super::store::Store<'a> for SceneManager<'a> {
-	fn receive_action(&'a mut self, action: &super::action::Action, dt: &f64) -> super::store::ReceiveActionReturnOption {
+impl super::store::Store for SceneManager {
+	fn receive_action(&mut self, action: &super::action::Action, ctx: &mut Context) -> StoreResult {
 		match action {
 			super::action::Action::MenuAction(menu_sub_action) => {
-				let mut out_actions: Vec<Action> = vec![];
+				if self.scenes.len() == 0 {
+					frame_warn!("No scenes yet ( ͡° ͜ʖ ͡°)");
+					return Ok(());
+				}
 
 				match menu_sub_action {
-					MenuSubAction::ChangeMenuStateAction => {
-						out_actions.push(
+					MenuSubAction::PushMenuAction(scene) => {
+						if *scene >= self.scenes.len() {
+							frame_warn!("There's no scene {} to open", scene);
+						}
+						self.open_scenes.push(*scene);
+						ctx.send(
 							Action::MenuAction(MenuSubAction::WaitForInputAction)
 						);
 					}
+					MenuSubAction::PopMenuAction => {
+						self.open_scenes.pop();
+						if !self.open_scenes.is_empty() {
+							ctx.send(
+								Action::MenuAction(MenuSubAction::DrawAction)
+							);
+						}
+					}
 					MenuSubAction::WaitForInputAction => {
-						out_actions.push(
+						ctx.send(
 							Action::MenuAction(MenuSubAction::DrawAction)
 						);
 					}
 					MenuSubAction::DrawAction => { //todo: make it actually draw stuff
-						out_actions.push(
+						ctx.send(
 							Action::MenuAction(MenuSubAction::WaitForInputAction)
 						);
 					}
 					_ => {}
 				}
 
-				match self.scenes.take() {
-					//As noted above, to understand this better look at the similar function in dispatcher.
-					Some(mut local_scenes) => {
-						for index in 0..local_scenes.len() {
-							match local_scenes[index].take() {
-								Some(scene) => {
-									match scene.receive_menu_sub_action(menu_sub_action) {
-										store::ReceiveMSAReturnOption::NewAction(out_msa_vec, out_reference) => {
-											for msa in out_msa_vec {
-												out_actions.push(Action::MenuAction(msa));
-											}
-											local_scenes[index] = Some(out_reference);
-										}
-										store::ReceiveMSAReturnOption::NoNewAction(out_reference) => {
-											local_scenes[index] = Some(out_reference);
-										}
-									}
-								}
-								None => println!("There is a missing scene...")
-							}
+				let scene = match self.get_current_scene() {
+					Some(index) => self.scenes.get_mut(index),
+					None => None,
+				};
+				match scene {
+					Some(scene) => {
+						for msa in scene.receive_menu_sub_action(menu_sub_action) {
+							ctx.send(Action::MenuAction(msa));
 						}
-
-						self.scenes = Some(local_scenes);
-						super::store::ReceiveActionReturnOption::NewAction(out_actions, false, self)
-					}
-					None => {
-						eprintln!("No scenes yet ( ͡° ͜ʖ ͡°)");
-						super::store::ReceiveActionReturnOption::NoNewAction(self)
 					}
+					None => {}
+				}
+			}
+			super::action::Action::AssetReloadAction(path, bytes) => {
+				for scene in self.scenes.iter_mut() {
+					scene.reload_asset(path, bytes);
 				}
 			}
-			_ => { super::store::ReceiveActionReturnOption::NoNewAction(self) }
+			_ => {}
 		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::MenuAction, ActionKind::AssetReloadAction])
 	}
 }
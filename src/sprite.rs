@@ -1,17 +1,20 @@
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
 
-use super::img::Img;
+use super::img::{Img, ImgHandle};
 
 pub struct Sprite {
 	///A sprite with animations (using the function animate)
 	pos: (u32, u32),
-	frames: Vec<Img>,
+	frames: Vec<ImgHandle>,
 	anims: Vec<(usize, usize)>,
 	current_anim: usize,
 	current_frame: usize,
 	dt_since_last_frame: f64,
 	framerate: f64,
+	source: Option<(PathBuf, usize)>,
+	// The file the frames come from and how many pixels it has per frame, see set_source.
 }
 
 /// Wrapper for the Img struct that handles animations and location.
@@ -19,12 +22,13 @@ impl Sprite {
 	pub fn new(pos: (u32, u32), frames: Vec<Img>, anims: Vec<(usize, usize)>, framerate: f64) -> Self {
 		Self {
 			pos,
-			frames,
+			frames: frames.into_iter().map(ImgHandle::new).collect(),
 			anims,
 			current_anim: 0,
 			current_frame: 0,
 			dt_since_last_frame: 0.0,
 			framerate,
+			source: None,
 		}
 	}
 
@@ -54,22 +58,82 @@ impl Sprite {
 		return 2 * (self.get_current_frame().get_length() / *self.get_current_frame().get_width()) as u32;
 	}
 
-	pub fn get_current_frame(&self) -> Img {
+	/// Returns a handle to the current frame. It stays the same as long as the frame is shown, so
+	/// the renderer only has to upload it once.
+	pub fn get_current_frame(&self) -> ImgHandle {
 		self.frames[self.current_frame].clone()
 	}
 
 	/// Wraps Img::new_from_file.
 	pub fn new_from_file(mut f: File, pix_per_frame: usize, pos: (u32, u32), anims: Vec<(usize, usize)>, framerate: f64) -> Self {
-		let mut width = 0;
-		let mut pixels = vec![];
-		let mut frames = vec![];
-		let real_pix_per_frame = pix_per_frame / 2;
-
 		let mut buf: Vec<u8> = vec![];
 		match f.read_to_end(&mut buf) {
 			Ok(_) => {},
-			Err(e) => eprintln!("Could not read image buffer: {}", e),
+			Err(e) => frame_error!("Could not read image buffer: {}", e),
+		}
+		return Self::new_from_bytes(&buf, pix_per_frame, pos, anims, framerate);
+	}
+
+	/// Swaps the frames for the ones in the contents of a .u4i file, e.g. when an asset was
+	/// reloaded. Position and animations stay as they are.
+	///
+	/// Fails and keeps the old frames if the file doesn't have enough frames for the animations,
+	/// which happens when it's read while an editor is still writing it.
+	pub fn reload_from_bytes(&mut self, buf: &[u8], pix_per_frame: usize) -> Result<(), String> {
+		if pix_per_frame < 2 {
+			return Err(format!("Can't split a sprite into frames of {} pixels", pix_per_frame));
+		}
+		let reloaded = Self::new_from_bytes(buf, pix_per_frame, self.pos, vec![], self.framerate);
+		let needed = self.anims.iter().map(|&(start, len)| start + len).max().unwrap_or(1).max(1);
+		if reloaded.frames.len() < needed {
+			return Err(format!("Reloaded sprite has {} frames, its animations need {}", reloaded.frames.len(), needed));
+		}
+		self.frames = reloaded.frames;
+		if self.current_frame >= self.frames.len() {
+			self.current_frame = 0;
+		}
+		Ok(())
+	}
+
+	/// Tells the sprite which .u4i file its frames come from, split into frames of
+	/// `pix_per_frame` pixels, so `reload_asset` knows which reloads are meant for it.
+	pub fn set_source<P: AsRef<Path>>(&mut self, path: P, pix_per_frame: usize) {
+		self.source = Some((path.as_ref().to_path_buf(), pix_per_frame));
+	}
+
+	pub fn get_source(&self) -> Option<&Path> {
+		match self.source {
+			Some((ref path, _)) => Some(path),
+			None => None,
+		}
+	}
+
+	/// Takes the new frames of an `AssetReloadAction` if the reloaded file is the sprite's source
+	/// (see `set_source`). The path only has to end with the source, so the source can be given
+	/// relative to the watched directory. Returns true if the frames were swapped.
+	///
+	/// A file that can't be used is logged and the old frames are kept, since the `AssetWatcher`
+	/// sends it again once the editor is done writing it.
+	pub fn reload_asset(&mut self, path: &Path, buf: &[u8]) -> bool {
+		let pix_per_frame = match self.source {
+			Some((ref source, pix_per_frame)) if path.ends_with(source) => pix_per_frame,
+			_ => return false,
+		};
+		match self.reload_from_bytes(buf, pix_per_frame) {
+			Ok(_) => true,
+			Err(e) => {
+				frame_warn!("Could not reload {}: {}", path.display(), e);
+				false
+			}
 		}
+	}
+
+	/// Wraps Img::new_from_bytes.
+	pub fn new_from_bytes(buf: &[u8], pix_per_frame: usize, pos: (u32, u32), anims: Vec<(usize, usize)>, framerate: f64) -> Self {
+		let mut width = 0;
+		let mut pixels = vec![];
+		let mut frames = vec![];
+		let real_pix_per_frame = pix_per_frame / 2;
 
 		if buf.len() < 4 {
 			return Self {
@@ -80,6 +144,7 @@ impl Sprite {
 				current_frame: 0,
 				dt_since_last_frame: 0.0,
 				framerate: 0.0,
+				source: None,
 			};
 		}
 
@@ -92,7 +157,7 @@ impl Sprite {
 				} else if (ctr - 4) % real_pix_per_frame == real_pix_per_frame - 1 {
 					pixels.push(*num);
 					frames.push(
-						Img::new_from_u8(width, pixels.clone())
+						ImgHandle::new(Img::new_from_u8(width, pixels.clone()))
 					);
 				} else {
 					pixels.push(*num);
@@ -111,6 +176,7 @@ impl Sprite {
 			current_frame: 0,
 			dt_since_last_frame: 0.0,
 			framerate,
+			source: None,
 		}
 	}
 }
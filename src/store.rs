@@ -1,19 +1,298 @@
 use super::action::Action;
-use super::action::MenuSubAction;
+use super::action::ActionKind;
+use super::action::Envelope;
+use super::clock::GameTime;
+use super::rng::GameRng;
+use super::timer::{Due, Timer, TimerId, MIN_INTERVAL};
 
-pub enum ReceiveActionReturnOption<'a> {
-	NoNewAction(&'a mut Store<'a>),
-	NewAction(Vec<Action>, /*add to secondary*/ bool, &'a mut Store<'a>),
+/// Identifies a store entered into a dispatcher. Stays the same for as long as the store is in
+/// there, no matter which other stores are added or removed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct StoreId(pub u64);
+
+/// Identifies a request sent with `Context::request`, so the answer can be matched up with it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct RequestId {
+	pub from: StoreId,
+	pub seq: u64,
+}
+
+/// Handed to a store together with every action. Everything a store wants the rest of the program
+/// to know goes through here, the dispatcher picks it up once `receive_action` returns.
+pub struct Context {
+	time: GameTime,
+	store_id: StoreId,
+	pub(crate) primary: Vec<Action>,
+	pub(crate) secondary: Vec<Action>,
+	pub(crate) added_stores: Vec<(StoreId, Box<Store>)>,
+	pub(crate) removed_stores: Vec<StoreId>,
+	pub(crate) next_store_id: u64,
+	pub(crate) timers: Vec<Timer>,
+	pub(crate) cancelled_timers: Vec<TimerId>,
+	pub(crate) next_timer_seq: u64,
+	pub(crate) next_request_seq: u64,
+	pub(crate) sender: Option<StoreId>,
+	pub(crate) request: Option<RequestId>,
+	pub(crate) reply_to: Option<RequestId>,
+	pub(crate) rng: GameRng,
+	pub(crate) frame_time: f64,
+}
+
+/// Everything a context collected except the added stores, which may not be sent to another
+/// thread. Used by the dispatcher to bring the results of stores running in parallel back.
+pub(crate) struct Sent {
+	pub(crate) primary: Vec<Action>,
+	pub(crate) secondary: Vec<Action>,
+	pub(crate) removed_stores: Vec<StoreId>,
+	pub(crate) timers: Vec<Timer>,
+	pub(crate) cancelled_timers: Vec<TimerId>,
+	pub(crate) next_timer_seq: u64,
+	pub(crate) next_request_seq: u64,
+	pub(crate) rng: GameRng,
 }
 
-pub enum ReceiveMSAReturnOption<'a> {
-	NoNewAction(&'a mut super::scene_manager::Scene<'a>),
-	NewAction(Vec<MenuSubAction>, &'a mut super::scene_manager::Scene<'a>),
+impl Context {
+	/// Creates a context for the store with the given id. The dispatcher does this for every call,
+	/// but it's also handy for testing a store on its own. The random number generator starts with
+	/// the seed 0.
+	pub fn new(time: GameTime, store_id: StoreId, next_store_id: u64) -> Self {
+		Self {
+			time,
+			store_id,
+			primary: vec![],
+			secondary: vec![],
+			added_stores: vec![],
+			removed_stores: vec![],
+			next_store_id,
+			timers: vec![],
+			cancelled_timers: vec![],
+			next_timer_seq: 0,
+			next_request_seq: 0,
+			sender: None,
+			request: None,
+			reply_to: None,
+			rng: GameRng::new(0),
+			frame_time: 0.0,
+		}
+	}
+
+	/// Returns the time of the current frame. It's the same for every store.
+	pub fn get_time(&self) -> &GameTime {
+		&self.time
+	}
+
+	/// Returns the real seconds the current frame took, not scaled by the time scale and not cut
+	/// into fixed steps. When replaying, it's the frame time of the recording. Meant for effects like the camera shake, which
+	/// should run at the same speed when the game is slowed down.
+	pub fn get_frame_time(&self) -> f64 {
+		self.frame_time
+	}
+
+	/// Returns the id of the store the action was handed to.
+	pub fn get_store_id(&self) -> StoreId {
+		self.store_id
+	}
+
+	/// Returns the dispatcher's random number generator. Stores that receive an action in
+	/// parallel each get a fork of it instead.
+	pub fn get_rng(&mut self) -> &mut GameRng {
+		&mut self.rng
+	}
+
+	/// Sends an action to the primary queue, so it's dispatched during this frame.
+	pub fn send(&mut self, action: Action) {
+		self.primary.push(action);
+	}
+
+	/// Sends an action to the secondary queue, so it's dispatched when there's time left.
+	pub fn send_secondary(&mut self, action: Action) {
+		self.secondary.push(action);
+	}
+
+	/// Returns everything sent to the primary and secondary queue so far.
+	pub fn get_sent(&self) -> (&Vec<Action>, &Vec<Action>) {
+		(&self.primary, &self.secondary)
+	}
+
+	/// Sends an action to a single store. It's dispatched from the primary queue like any other
+	/// action, but only the given store receives it, whether it subscribed to its kind or not.
+	pub fn send_to(&mut self, to: StoreId, action: Action) {
+		let from = Some(self.store_id);
+		self.primary.push(Action::DirectedAction(Envelope {
+			to,
+			from,
+			request: None,
+			reply_to: None,
+			action: Box::new(action),
+		}));
+	}
+
+	/// Sends an action to a single store and asks for an answer. The answer comes back as an
+	/// action for this store only, with `get_reply_to` returning the id handed out here.
+	pub fn request(&mut self, to: StoreId, action: Action) -> RequestId {
+		let id = RequestId {
+			from: self.store_id,
+			seq: self.next_request_seq,
+		};
+		self.next_request_seq += 1;
+		self.primary.push(Action::DirectedAction(Envelope {
+			to,
+			from: Some(self.store_id),
+			request: Some(id),
+			reply_to: None,
+			action: Box::new(action),
+		}));
+		return id;
+	}
+
+	/// Answers the request that is being handled. Returns false if the current action isn't a
+	/// request, in which case nothing is sent.
+	pub fn reply(&mut self, action: Action) -> bool {
+		match (self.sender, self.request) {
+			(Some(to), Some(request)) => {
+				self.primary.push(Action::DirectedAction(Envelope {
+					to,
+					from: Some(self.store_id),
+					request: None,
+					reply_to: Some(request),
+					action: Box::new(action),
+				}));
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Returns the store that sent the current action directly to this one. `None` for broadcast
+	/// actions.
+	pub fn get_sender(&self) -> Option<StoreId> {
+		self.sender
+	}
+
+	/// Returns the id of the request being handled, if the sender waits for an answer.
+	pub fn get_request(&self) -> Option<RequestId> {
+		self.request
+	}
+
+	/// Returns the id of the request the current action answers, if it is an answer.
+	pub fn get_reply_to(&self) -> Option<RequestId> {
+		self.reply_to
+	}
+
+	/// Enters a new store into the dispatcher. It receives everything dispatched from the next
+	/// action on.
+	///
+	/// Not possible while the action is handed to stores in parallel, see
+	/// `Dispatcher::enable_parallel_dispatch`: the store is dropped and the call counts as an error
+	/// of the calling store. Send an action to a store on the main thread instead.
+	pub fn add_store(&mut self, store: Box<Store>) -> StoreId {
+		let id = StoreId(self.next_store_id);
+		self.next_store_id += 1;
+		self.added_stores.push((id, store));
+		return id;
+	}
+
+	/// Removes a store from the dispatcher. Works for the calling store, too.
+	pub fn remove_store(&mut self, id: StoreId) {
+		self.removed_stores.push(id);
+	}
+
+	/// Sends an action to the primary queue once `delay` seconds of game time have passed.
+	pub fn send_after(&mut self, delay: f64, action: Action) -> TimerId {
+		let id = self.next_timer_id();
+		let due = Due::Time(self.time.total + delay);
+		self.timers.push(Timer::once(id, due, action));
+		return id;
+	}
+
+	/// Sends an action to the primary queue at the start of the given frame, or at the start of
+	/// the next one if that frame has already begun.
+	pub fn send_at_frame(&mut self, frame: u64, action: Action) -> TimerId {
+		let id = self.next_timer_id();
+		self.timers.push(Timer::once(id, Due::Frame(frame), action));
+		return id;
+	}
+
+	/// Sends the action built by `make` every `interval` seconds of game time, starting in
+	/// `interval` seconds, until the timer is cancelled. Intervals below `timer::MIN_INTERVAL`
+	/// are stretched to it.
+	pub fn send_every(&mut self, interval: f64, make: Box<FnMut() -> Action + Send>) -> TimerId {
+		let id = self.next_timer_id();
+		let first = self.time.total + interval.max(MIN_INTERVAL);
+		self.timers.push(Timer::every(id, first, interval, make));
+		return id;
+	}
+
+	/// Cancels a timer, no matter which store started it.
+	pub fn cancel_timer(&mut self, id: TimerId) {
+		self.cancelled_timers.push(id);
+	}
+
+	fn next_timer_id(&mut self) -> TimerId {
+		let id = TimerId {
+			owner: Some(self.store_id),
+			seq: self.next_timer_seq,
+		};
+		self.next_timer_seq += 1;
+		return id;
+	}
+
+	/// Splits off the added stores from everything else the store sent.
+	pub(crate) fn split(self) -> (Vec<(StoreId, Box<Store>)>, Sent) {
+		(self.added_stores, Sent {
+			primary: self.primary,
+			secondary: self.secondary,
+			removed_stores: self.removed_stores,
+			timers: self.timers,
+			cancelled_timers: self.cancelled_timers,
+			next_timer_seq: self.next_timer_seq,
+			next_request_seq: self.next_request_seq,
+			rng: self.rng,
+		})
+	}
 }
 
+/// What `Store::receive_action` returns. The error says what went wrong, for the log and for the
+/// `StoreErrorAction` other stores get.
+pub type StoreResult = Result<(), String>;
+
 /// Turns any boring struct in a Store in the Flux pattern sense. These objects contain all the pro-
 /// grams logic and communicate by ways of actions through the dispatcher.
-pub trait Store<'a> {
-	///The function that is called by the dispatcher to hand over a reference to an action.
-	fn receive_action(&'a mut self, action: &Action, dt: &f64) -> ReceiveActionReturnOption<'a>;
+pub trait Store {
+	///The function that is called by the dispatcher to hand over a reference to an action. New
+	/// actions are sent through the context.
+	///
+	/// An error is handed to the dispatcher, which deals with it according to its `ErrorPolicy`.
+	/// Whatever the store sent before failing is still taken care of.
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult;
+
+	/// The kinds of actions this store wants to receive. Asked once, when the store is entered into
+	/// the dispatcher. `None` (the default) means every action.
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		None
+	}
+
+	/// Whether this store turns keyboard, mouse or window events into actions. The dispatcher
+	/// records that player input separately and replaces it when replaying a recording.
+	fn is_input_source(&self) -> bool {
+		false
+	}
+
+	/// The key this store's snapshot is saved under. Stores that return `None` (the default)
+	/// don't take part in saving and loading. The key has to stay the same between runs of the
+	/// game, and has to be unique among the stores.
+	fn save_key(&self) -> Option<String> {
+		None
+	}
+
+	/// Returns a snapshot of everything the store needs to pick up where it left off. Only called
+	/// if there is a save key.
+	fn save(&self) -> Vec<u8> {
+		vec![]
+	}
+
+	/// Restores the store from a snapshot taken by `save`, possibly in an earlier run.
+	fn load(&mut self, _data: &[u8]) -> Result<(), String> {
+		Ok(())
+	}
 }
@@ -1,52 +1,47 @@
-use action::Action;
+use action::{Action, ActionKind, Depth, Layer};
 use sprite::Sprite;
-use store::ReceiveActionReturnOption;
-use store::Store;
+use store::Context;
+use store::{Store, StoreResult};
 
 pub struct TestObject {
 	///A game object for testing
-	sprite: Option<Sprite>,
+	sprite: Sprite,
 }
 
-impl<'a> TestObject {
+impl TestObject {
 	pub fn new(sprite: Sprite) -> Self {
 		Self {
-			sprite: Some(sprite),
+			sprite,
 		}
 	}
 
-	fn send_frame(&'a mut self, dt: f64) -> Action {
-		let mut out_action = Action::EmptyAction;
-
-		match self.sprite.take() {
-			Some(mut sprite) => {
-				sprite.animate(dt);
-				out_action = Action::AddImgToCanvasAction(
-					sprite.get_pos().0,
-					sprite.get_pos().1,
-					sprite.get_current_frame(),
-				);
-				self.sprite = Some(sprite);
-			}
-			None => {}
-		}
-		return out_action;
+	fn send_frame(&mut self, dt: f64) -> Action {
+		self.sprite.animate(dt);
+		return Action::AddImgToCanvasAction(
+			self.sprite.get_pos().0 as i32,
+			self.sprite.get_pos().1 as i32,
+			Depth::new(Layer::World, 0),
+			self.sprite.get_current_frame(),
+		);
 	}
 }
 
-impl<'a> Store<'a> for TestObject {
-	fn receive_action(&'a mut self, action: &Action, dt: &f64) -> ReceiveActionReturnOption<'a> {
+impl Store for TestObject {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
 		match action {
-			&Action::SendFrameAction(w, h, p) => {
-				return ReceiveActionReturnOption::NewAction(
-					vec!(
-						self.send_frame(dt.clone())
-					),
-					false,
-					self,
-				);
+			&Action::SendFrameAction => {
+				let dt = ctx.get_time().dt;
+				ctx.send(self.send_frame(dt));
 			}
-			_ => return ReceiveActionReturnOption::NoNewAction(self)
+			&Action::AssetReloadAction(ref path, ref bytes) => {
+				self.sprite.reload_asset(path, bytes);
+			}
+			_ => {}
 		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::SendFrameAction, ActionKind::AssetReloadAction])
 	}
 }
\ No newline at end of file
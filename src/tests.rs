@@ -1,52 +1,90 @@
-/// The tests file. Tests should be run with the --test-threads 1 option so they don't block the
-/// SDL environment for each other.
+/// The tests file. Tests that open a window should be run with the --test-threads 1 option so they
+/// don't block the SDL environment for each other. Tests using `Renderer::new_headless` don't need it.
 
-use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::Read;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::ThreadId;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use rand;
+use rand::{Rng, RngCore};
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
 use sdl2::video::Window;
 
 use action::MenuSubAction;
 use button::Button;
 use scene_manager::MinimalScene;
+use scene_manager::Scene;
 use scene_manager::SceneManager;
-use test_object::TestObj;
+use test_object::TestObject;
 
 use super::action::Action;
-use super::dispatcher::Dispatcher;
-use super::img::Img;
+use super::action::ActionKind;
+use super::action::CustomPayload;
+use super::action::{CameraSubAction, Depth, Layer};
+use super::camera::Camera;
+use super::clock::{ClockMode, GameClock, GameTime};
+use super::dispatcher::{Dispatcher, ErrorPolicy};
+use super::middleware::{DebounceMiddleware, FilterMiddleware, Middleware};
+use super::asset_watcher::AssetWatcher;
+use super::headless::HeadlessDriver;
+use super::img::{Img, ImgHandle};
+use super::log;
+use super::log::{Level, RingBuffer, Sink};
+use super::recorder::encode_action;
+use super::save::SaveFile;
 use super::renderer::Renderer;
 use super::sprite::Sprite;
-use super::store::ReceiveActionReturnOption;
+use super::store::Context;
 use super::store::Store;
+use super::store::StoreResult;
+use super::store::RequestId;
+use super::store::StoreId;
+use super::timer::{Timer, TimerId, Timers, MAX_CATCH_UP};
+use super::{run_frames, run_until};
+
+/// A game-defined action, carries a number from one store to another.
+struct TestAction(u8);
+
+impl CustomPayload for TestAction {
+	fn describe(&self) -> String {
+		format!("TestAction {}", self.0)
+	}
+}
 
 struct TestStore {
 	in_num: u8,
-	out_num: u8,
+	out_num: Rc<Cell<u8>>,
 }
 
-impl<'a> Store<'a> for TestStore {
-	fn receive_action(&'a mut self, action: &Action, dt: &f64) -> ReceiveActionReturnOption<'a> {
+impl Store for TestStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action.get_custom::<TestAction>() {
+			Some(&TestAction(num)) => self.out_num.set(num),
+			None => {}
+		}
 		match action {
-			&Action::TestAction(num) => {
-				self.out_num = num;
-				return ReceiveActionReturnOption::NoNewAction(self);
-			}
 			&Action::EndFrameAction => {
-				return ReceiveActionReturnOption::NewAction(
-					vec!(
-						Action::TestAction(self.in_num)
-					),
-					false,
-					self,
-				);
+				ctx.send(Action::custom(TestAction(self.in_num)));
 			}
-			_ => return ReceiveActionReturnOption::NoNewAction(self)
+			_ => {}
 		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::custom::<TestAction>(), ActionKind::EndFrameAction])
 	}
 }
 
@@ -62,18 +100,1379 @@ fn test_dispatcher() {
 		test_num = rand::random()
 	}
 
-	let mut test_store = TestStore { in_num: test_num, out_num: 0 };
+	let out_num = Rc::new(Cell::new(0));
+	let test_store = TestStore { in_num: test_num, out_num: out_num.clone() };
 
 	{
 		let mut disp = Dispatcher::new(1.0 / 60.0);
-		disp.enter_refs(vec!(&mut test_store));
+		disp.enter_stores(vec!(Box::new(test_store)));
 
 		for i in 1..10 {
 			disp.dispatch();
 		}
 	}
 
-	assert_eq!(test_num, test_store.out_num)
+	assert_eq!(test_num, out_num.get())
+}
+
+struct CountingStore {
+	kinds: Option<Vec<ActionKind>>,
+	received: Rc<Cell<u32>>,
+}
+
+impl Store for CountingStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		self.received.set(self.received.get() + 1);
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		self.kinds.clone()
+	}
+}
+
+#[test]
+fn test_subscriptions() {
+	/*a store only subscribed to keyboard actions should never be called, while a store without
+	subscriptions hears everything the TestStore sends*/
+	let out_num = Rc::new(Cell::new(0));
+	let keyboard_received = Rc::new(Cell::new(0));
+	let wildcard_received = Rc::new(Cell::new(0));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.enter_stores(vec!(
+			Box::new(TestStore { in_num: 1, out_num: out_num.clone() }),
+			Box::new(CountingStore { kinds: Some(vec![ActionKind::KeyboardAction]), received: keyboard_received.clone() }),
+			Box::new(CountingStore { kinds: None, received: wildcard_received.clone() }),
+		));
+
+		for i in 1..10 {
+			disp.dispatch();
+		}
+	}
+
+	assert_eq!(out_num.get(), 1);
+	assert_eq!(keyboard_received.get(), 0);
+	assert!(wildcard_received.get() > 0);
+}
+
+struct ClockStore {
+	send_updates: bool,
+	times: Rc<RefCell<Vec<GameTime>>>,
+}
+
+impl Store for ClockStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::UpdateAction => {
+				self.times.borrow_mut().push(ctx.get_time().clone());
+			}
+			&Action::EndFrameAction if self.send_updates => {
+				ctx.send(Action::UpdateAction);
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn test_fixed_clock() {
+	/*in fixed mode every update step has the same dt and every store sees the same time, no matter
+	in which order they were entered*/
+	let step = 0.002;
+	let first_times = Rc::new(RefCell::new(vec![]));
+	let second_times = Rc::new(RefCell::new(vec![]));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.set_clock_mode(ClockMode::Fixed(step)).unwrap();
+		disp.enter_stores(vec!(
+			Box::new(ClockStore { send_updates: true, times: first_times.clone() }),
+			Box::new(ClockStore { send_updates: false, times: second_times.clone() }),
+		));
+
+		for i in 1..200 {
+			disp.dispatch();
+			sleep(Duration::from_micros(200));
+		}
+	}
+
+	assert!(first_times.borrow().len() > 0);
+	assert_eq!(first_times, second_times);
+	for (index, time) in first_times.borrow().iter().enumerate() {
+		assert_eq!(time.dt, step);
+		assert!((time.total - step * (index + 1) as f64).abs() < 1e-9);
+	}
+}
+
+struct InputStore {
+	keys: Vec<Scancode>,
+	received: Rc<RefCell<Vec<Scancode>>>,
+}
+
+impl Store for InputStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::EndFrameAction => {
+				if self.keys.len() > 0 {
+					let frame = ctx.get_time().frame as usize;
+					ctx.send(Action::KeyboardAction(self.keys[frame % self.keys.len()]));
+				}
+				ctx.send(Action::UpdateAction);
+			}
+			&Action::KeyboardAction(scancode) => {
+				self.received.borrow_mut().push(scancode);
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn is_input_source(&self) -> bool {
+		true
+	}
+}
+
+#[test]
+fn test_record_and_replay() {
+	/*records a run with some keyboard input, then replays it with an input source that doesn't send
+	anything. The replay should receive the recorded input and dispatch the same actions*/
+	let path = env::temp_dir().join("u4x_test_record_and_replay.rec");
+	let recorded = Rc::new(RefCell::new(vec![]));
+	let replayed = Rc::new(RefCell::new(vec![]));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.record_to(&path).unwrap();
+		disp.enter_stores(vec!(
+			Box::new(InputStore { keys: vec![Scancode::A, Scancode::B, Scancode::C], received: recorded.clone() }),
+		));
+
+		for i in 1..30 {
+			disp.dispatch();
+		}
+		disp.drop_stores();
+	}
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.replay_from(&path).unwrap();
+		disp.enter_stores(vec!(Box::new(InputStore { keys: vec![], received: replayed.clone() })));
+
+		while disp.dispatch() {};
+
+		assert_eq!(disp.get_replay_divergence(), None);
+	}
+
+	assert!(recorded.borrow().len() > 0);
+	assert_eq!(recorded, replayed);
+}
+
+#[test]
+fn test_profiling() {
+	/*profiles a few frames of the TestStore and checks that its calls show up in the frame summary
+	and in the Chrome trace*/
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enable_profiling();
+	disp.enter_stores(vec!(Box::new(TestStore { in_num: 1, out_num: Rc::new(Cell::new(0)) })));
+
+	for i in 1..20 {
+		disp.dispatch();
+	}
+
+	{
+		let profiler = disp.get_profiler().unwrap();
+		let last_frame = profiler.get_last_frame().unwrap();
+		assert!(last_frame.stores.iter().any(|stat| stat.store == StoreId(0) && stat.kind == ActionKind::custom::<TestAction>()));
+
+		let mut trace: Vec<u8> = vec![];
+		profiler.write_chrome_trace(&mut trace).unwrap();
+		let trace = String::from_utf8(trace).unwrap();
+		assert!(trace.starts_with("{\"traceEvents\":["));
+		assert!(trace.contains("\"name\":\"EndFrameAction\""));
+	}
+
+	// only the last frames are kept
+	disp.get_profiler_mut().unwrap().set_max_frames(3);
+	for i in 1..20 {
+		disp.dispatch();
+	}
+	{
+		let profiler = disp.get_profiler().unwrap();
+		assert_eq!(profiler.get_frames().len(), 3);
+		let oldest = profiler.get_frames()[0].frame;
+		assert!(profiler.get_samples().iter().all(|sample| sample.frame >= oldest));
+	}
+
+	assert!(disp.disable_profiling().is_some());
+	assert!(disp.get_profiler().is_none());
+}
+
+struct RemoverStore {
+	target: Option<StoreId>,
+	spawn: Option<Box<Store>>,
+}
+
+impl Store for RemoverStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match (action, self.target.take()) {
+			(&Action::EndFrameAction, Some(target)) => {
+				ctx.send(Action::RemoveStoreAction(target));
+			}
+			_ => {}
+		}
+		match self.spawn.take() {
+			Some(store) => {
+				ctx.add_store(store);
+				ctx.remove_store(ctx.get_store_id());
+			}
+			None => {}
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn test_add_and_remove_stores() {
+	/*a store removed through an action stops receiving actions, a store added while running starts
+	receiving them, and the ids of the remaining stores don't change. A store can also spawn another
+	store and remove itself through its context*/
+	let removed_received = Rc::new(Cell::new(0));
+	let added_received = Rc::new(Cell::new(0));
+	let spawned_received = Rc::new(Cell::new(0));
+	let removed_count;
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		let ids = disp.enter_stores(vec!(Box::new(CountingStore { kinds: None, received: removed_received.clone() })));
+		for i in 1..5 {
+			disp.dispatch();
+		}
+
+		let remover_id = disp.add_store(Box::new(RemoverStore { target: Some(ids[0]), spawn: None }));
+		let added_id = disp.add_store(Box::new(CountingStore { kinds: None, received: added_received.clone() }));
+		disp.add_store(Box::new(RemoverStore {
+			target: None,
+			spawn: Some(Box::new(CountingStore { kinds: None, received: spawned_received.clone() })),
+		}));
+		for i in 1..20 {
+			disp.dispatch();
+		}
+
+		let store_ids = disp.get_store_ids();
+		assert_eq!(store_ids.len(), 3);
+		assert_eq!(store_ids[0..2], [remover_id, added_id]);
+		assert!(disp.remove_store(ids[0]).is_none());
+		assert!(disp.remove_store(added_id).is_some());
+		removed_count = removed_received.get();
+	}
+
+	assert!(removed_count > 0);
+	assert!(added_received.get() > removed_count);
+	assert!(spawned_received.get() > 0);
+}
+
+struct TimerStore {
+	received: Rc<RefCell<Vec<(u64, u8)>>>,
+	repeating: Option<TimerId>,
+}
+
+impl Store for TimerStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::StartAction => {
+				ctx.send_at_frame(5, Action::custom(TestAction(5)));
+				let cancelled = ctx.send_at_frame(3, Action::custom(TestAction(3)));
+				ctx.cancel_timer(cancelled);
+				self.repeating = Some(ctx.send_every(0.005, Box::new(|| Action::custom(TestAction(1)))));
+			}
+			_ => {}
+		}
+		match action.get_custom::<TestAction>() {
+			Some(&TestAction(num)) => {
+				self.received.borrow_mut().push((ctx.get_time().frame, num));
+				let repeated = self.received.borrow().iter().filter(|&&(_, num)| num == 1).count();
+				match self.repeating {
+					Some(id) if repeated == 3 => ctx.send(Action::CancelTimerAction(id)),
+					_ => {}
+				}
+			}
+			None => {}
+		}
+		Ok(())
+	}
+}
+
+#[test]
+fn test_timers() {
+	/*a store schedules an action for frame 5, one for frame 3 that it cancels right away and a
+	repeating one that it cancels after it fired three times*/
+	let received = Rc::new(RefCell::new(vec![]));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.enter_stores(vec!(Box::new(TimerStore { received: received.clone(), repeating: None })));
+
+		for i in 1..300 {
+			disp.dispatch();
+			sleep(Duration::from_millis(1));
+		}
+
+		assert_eq!(disp.get_timer_count(), 0);
+	}
+
+	let received = received.borrow();
+	assert_eq!(received.iter().filter(|&&(_, num)| num == 5).collect::<Vec<_>>(), vec![&(5, 5)]);
+	assert!(!received.iter().any(|&(_, num)| num == 3));
+	// a slow frame can fire the repeating timer more than once before the cancel goes through
+	assert!(received.iter().filter(|&&(_, num)| num == 1).count() >= 3);
+
+	// an interval of 0 doesn't hang, and a long frame only catches up so far
+	let mut timers = Timers::new();
+	timers.add(Timer::every(TimerId { owner: None, seq: 0 }, 0.0, 0.0, Box::new(|| Action::EmptyAction)));
+	let time = GameTime { dt: 100.0, frame: 1, total: 100.0 };
+	assert_eq!(timers.fire(&time).len(), MAX_CATCH_UP as usize);
+	assert_eq!(timers.fire(&time).len(), 0);
+}
+
+/// Answers every update with its number and notes which thread it ran on.
+struct WorkerStore {
+	num: u8,
+	threads: Arc<Mutex<Vec<ThreadId>>>,
+}
+
+impl Store for WorkerStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		self.threads.lock().unwrap().push(thread::current().id());
+		ctx.send(Action::custom(TestAction(self.num)));
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::UpdateAction])
+	}
+}
+
+struct CollectStore {
+	received: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Store for CollectStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action.get_custom::<TestAction>() {
+			Some(&TestAction(num)) => self.received.borrow_mut().push(num),
+			None => {}
+		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::custom::<TestAction>()])
+	}
+}
+
+#[test]
+fn test_parallel_dispatch() {
+	/*50 stores get every update on four worker threads. The actions they send have to arrive in
+	store order all the same*/
+	let threads = Arc::new(Mutex::new(vec![]));
+	let received = Rc::new(RefCell::new(vec![]));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.enable_parallel_dispatch(vec![ActionKind::UpdateAction], 4);
+		disp.enter_stores(vec!(
+			Box::new(ClockStore { send_updates: true, times: Rc::new(RefCell::new(vec![])) }),
+			Box::new(CollectStore { received: received.clone() }),
+		));
+		for num in 0..50 {
+			disp.add_send_store(Box::new(WorkerStore { num, threads: threads.clone() }));
+		}
+
+		for i in 1..100 {
+			disp.dispatch();
+		}
+	}
+
+	let received = received.borrow();
+	assert!(received.len() >= 50);
+	for (index, num) in received.iter().enumerate() {
+		assert_eq!(*num as usize, index % 50);
+	}
+	let main_thread = thread::current().id();
+	let threads = threads.lock().unwrap();
+	assert!(threads.iter().all(|&id| id != main_thread));
+	// the same four threads are used for every update
+	assert!(threads.iter().collect::<HashSet<_>>().len() <= 4);
+}
+
+/// Asks the renderer for the canvas size once and sends a number to another store directly.
+struct AskingStore {
+	renderer: StoreId,
+	friend: StoreId,
+	request: Option<RequestId>,
+	answers: Rc<RefCell<Vec<(u32, u32, u32)>>>,
+}
+
+impl Store for AskingStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::StartAction => {
+				self.request = Some(ctx.request(self.renderer, Action::GetCanvasSizeAction));
+				ctx.send_to(self.friend, Action::custom(TestAction(7)));
+			}
+			&Action::CanvasSizeAction(w, h, p) => {
+				assert_eq!(ctx.get_sender(), Some(self.renderer));
+				assert_eq!(ctx.get_reply_to(), self.request);
+				self.answers.borrow_mut().push((w, h, p));
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::StartAction])
+	}
+}
+
+#[test]
+fn test_directed_actions() {
+	/*the answer to a request only reaches the store that asked, and a directed action reaches a
+	store that didn't subscribe to its kind*/
+	let answers = Rc::new(RefCell::new(vec![]));
+	let friend_received = Rc::new(Cell::new(0));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		let ids = disp.enter_stores(vec!(
+			Box::new(Renderer::new_headless(256, 128, None)),
+			Box::new(CountingStore { kinds: Some(vec![ActionKind::CanvasSizeAction]), received: friend_received.clone() }),
+		));
+		disp.enter_stores(vec!(Box::new(AskingStore { renderer: ids[0], friend: ids[1], request: None, answers: answers.clone() })));
+
+		for i in 1..20 {
+			disp.dispatch();
+		}
+	}
+
+	assert_eq!(*answers.borrow(), vec![(256, 128, 8)]);
+	// the number sent to it directly, but not the answer meant for the asking store
+	assert_eq!(friend_received.get(), 1);
+}
+
+#[test]
+fn test_menu_layers() {
+	/*opens the pause menu with Escape, an options menu on top of it, and closes both with Escape.
+	The clock only runs again once both are closed. An update is kept behind every menu action,
+	the way gameplay actions pile up while a menu is open*/
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.dispatch();
+	disp.dispatch();
+	assert!(disp.get_menu_layers().is_empty());
+
+	disp.add_action_primary(Action::MenuAction(MenuSubAction::ChangeMenuStateAction));
+	disp.dispatch();
+	assert_eq!(disp.get_menu_layers().iter().map(|layer| layer.scene).collect::<Vec<_>>(), vec![0]);
+	assert!(disp.get_clock().is_paused());
+
+	disp.add_action_primary(Action::MenuAction(MenuSubAction::PushMenuAction(1)));
+	disp.add_action_primary(Action::UpdateAction);
+	disp.dispatch();
+	assert_eq!(disp.get_menu_layers().iter().map(|layer| layer.scene).collect::<Vec<_>>(), vec![0, 1]);
+
+	disp.add_action_primary(Action::MenuAction(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(Action::UpdateAction);
+	disp.dispatch();
+	assert_eq!(disp.get_menu_layers().iter().map(|layer| layer.scene).collect::<Vec<_>>(), vec![0]);
+	assert!(disp.get_clock().is_paused());
+
+	disp.add_action_primary(Action::MenuAction(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(Action::UpdateAction);
+	disp.dispatch();
+	assert!(disp.get_menu_layers().is_empty());
+	assert!(!disp.get_clock().is_paused());
+}
+
+/// Keeps a number that survives saving and loading.
+struct SavingStore {
+	key: &'static str,
+	value: Rc<Cell<u32>>,
+}
+
+impl Store for SavingStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![])
+	}
+
+	fn save_key(&self) -> Option<String> {
+		Some(String::from(self.key))
+	}
+
+	fn save(&self) -> Vec<u8> {
+		format!("{}", self.value.get()).into_bytes()
+	}
+
+	fn load(&mut self, data: &[u8]) -> Result<(), String> {
+		match String::from_utf8_lossy(data).parse() {
+			Ok(value) => {
+				self.value.set(value);
+				Ok(())
+			}
+			Err(e) => Err(format!("{}", e)),
+		}
+	}
+}
+
+#[test]
+fn test_save_and_load() {
+	/*two stores are saved through an action, changed and loaded again. A store without a save key
+	isn't touched, and a file that isn't a save is refused*/
+	let path = env::temp_dir().join("u4x_test_save_and_load.sav");
+	let first = Rc::new(Cell::new(3));
+	let second = Rc::new(Cell::new(7));
+	let unsaved = Rc::new(Cell::new(0));
+
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enter_stores(vec!(
+		Box::new(SavingStore { key: "first", value: first.clone() }),
+		Box::new(CountingStore { kinds: Some(vec![]), received: unsaved.clone() }),
+		Box::new(SavingStore { key: "second", value: second.clone() }),
+	));
+	disp.add_action_primary(Action::SaveAction(path.clone()));
+	for i in 0..5 {
+		disp.dispatch();
+	}
+	assert_eq!(disp.take_snapshot().get_keys(), vec!["first", "second"]);
+
+	first.set(30);
+	second.set(70);
+	unsaved.set(100);
+	disp.add_action_primary(Action::LoadAction(path.clone()));
+	while first.get() == 30 {
+		disp.dispatch();
+	}
+	assert_eq!(first.get(), 3);
+	assert_eq!(second.get(), 7);
+	assert_eq!(unsaved.get(), 100);
+
+	let save = SaveFile::open(&path).unwrap();
+	assert_eq!(save.get("second"), Some(&b"7"[..]));
+
+	let recording = env::temp_dir().join("u4x_test_save_and_load.rec");
+	disp.record_to(&recording).unwrap();
+	disp.stop_recording();
+	assert!(disp.load_from(&recording).is_err());
+
+	// a broken length is an error, not a huge allocation
+	let broken: &[u8] = b"U4XS\x02\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\xFF\xFF\xFF\xFFkey";
+	assert_eq!(SaveFile::read(&mut &broken[..]).err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+/// Turns every reloaded asset into an image.
+struct ReloadStore {
+	reloaded: Rc<RefCell<Vec<(String, usize)>>>,
+}
+
+impl Store for ReloadStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::AssetReloadAction(ref path, ref bytes) => {
+				let name = path.file_name().unwrap().to_string_lossy().into_owned();
+				self.reloaded.borrow_mut().push((name, Img::new_from_bytes(bytes).get_length()));
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+}
+
+/// Keeps the pixels of the last image sent to the canvas.
+struct CanvasStore {
+	pixels: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Store for CanvasStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::AddImgToCanvasAction(_, _, _, ref img) => *self.pixels.borrow_mut() = img.get_pixels().clone(),
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::AddImgToCanvasAction])
+	}
+}
+
+#[test]
+fn test_asset_reload() {
+	/*images that are changed or added while the game runs are sent to the stores, files that were
+	there from the start or have another extension are not. A game object whose sprite comes from
+	a changed file shows the new frames*/
+	let dir = env::temp_dir().join("u4x_test_asset_reload");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir).unwrap();
+	fs::write(dir.join("ship.u4i"), [0, 0, 0, 4, 0x01, 0x23]).unwrap();
+	fs::write(dir.join("notes.txt"), "hello").unwrap();
+
+	let reloaded = Rc::new(RefCell::new(vec![]));
+	let pixels = Rc::new(RefCell::new(vec![]));
+	let mut ship = Sprite::new_from_bytes(&fs::read(dir.join("ship.u4i")).unwrap(), 4, (0, 0), vec![(0, 1)], 1.0);
+	ship.set_source("ship.u4i", 4);
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enter_stores(vec!(
+		Box::new(AssetWatcher::new(&dir, Duration::from_millis(0)).with_extension("u4i")),
+		Box::new(ReloadStore { reloaded: reloaded.clone() }),
+		Box::new(HeadlessDriver::new()),
+		Box::new(TestObject::new(ship)),
+		Box::new(CanvasStore { pixels: pixels.clone() }),
+	));
+	for i in 0..10 {
+		disp.dispatch();
+	}
+	assert!(reloaded.borrow().is_empty());
+	assert_eq!(*pixels.borrow(), vec![0x01, 0x23]);
+
+	fs::write(dir.join("ship.u4i"), [0, 0, 0, 4, 0x89, 0xAB, 0x45, 0x67]).unwrap();
+	fs::write(dir.join("rock.u4i"), [0, 0, 0, 2, 0xFF]).unwrap();
+	fs::write(dir.join("notes.txt"), "hello again").unwrap();
+	while reloaded.borrow().len() < 2 {
+		disp.dispatch();
+	}
+	for i in 0..10 {
+		disp.dispatch();
+	}
+	assert_eq!(*reloaded.borrow(), vec![(String::from("rock.u4i"), 1), (String::from("ship.u4i"), 4)]);
+	assert_eq!(*pixels.borrow(), vec![0x89, 0xAB]);
+
+	let mut sprite = Sprite::new_from_bytes(&[0, 0, 0, 4, 0x01, 0x23], 4, (3, 4), vec![(0, 1)], 1.0);
+	sprite.reload_from_bytes(&[0, 0, 0, 4, 0x01, 0x23, 0x45, 0x67], 4).unwrap();
+	assert_eq!(sprite.get_pos(), (3, 4));
+	assert_eq!(sprite.get_current_frame().get_length(), 2);
+
+	//a file that is still being written keeps the old frames
+	let mut sprite = Sprite::new_from_bytes(&[0, 0, 0, 4, 0x01, 0x23, 0x45, 0x67], 4, (0, 0), vec![(0, 2)], 1.0);
+	sprite.animate(1.0);
+	assert!(sprite.reload_from_bytes(&[], 4).is_err());
+	assert!(sprite.reload_from_bytes(&[0, 0, 0, 4, 0x89, 0xAB, 0xCD], 4).is_err());
+	assert_eq!(*sprite.get_current_frame().get_pixels(), vec![0x45, 0x67]);
+	sprite.animate(1.0);
+	assert_eq!(*sprite.get_current_frame().get_pixels(), vec![0x01, 0x23]);
+}
+
+#[test]
+fn test_time_scale() {
+	/*slow motion halves dt, frozen time keeps it at 0 until a single frame is stepped. In fixed mode
+	the time scale changes the number of steps*/
+	let mut clock = GameClock::new(ClockMode::Variable);
+	clock.set_time_scale(0.5);
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.get_time().dt, 0.05);
+
+	clock.set_time_scale(0.0);
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.get_time().dt, 0.0);
+	assert_eq!(clock.get_time().total, 0.05);
+	clock.step_frame();
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.get_time().dt, 0.1);
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.get_time().dt, 0.0);
+
+	let mut clock = GameClock::new(ClockMode::Fixed(0.1));
+	clock.set_time_scale(2.0);
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.take_update_steps(), 2);
+	clock.set_time_scale(0.0);
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.take_update_steps(), 0);
+	clock.step_frame();
+	clock.start_frame_with(0.1);
+	assert_eq!(clock.take_update_steps(), 1);
+
+	//steps that would never be used up are rejected, and a long frame only catches up so far
+	assert!(clock.set_mode(ClockMode::Fixed(0.0)).is_err());
+	assert!(clock.set_mode(ClockMode::Fixed(-1.0)).is_err());
+	assert!(clock.set_mode(ClockMode::Fixed(::std::f64::NAN)).is_err());
+	assert_eq!(clock.get_mode(), ClockMode::Fixed(0.1));
+	assert_eq!(GameClock::new(ClockMode::Fixed(0.0)).get_mode(), ClockMode::Variable);
+	clock.set_time_scale(1.0);
+	clock.set_max_steps(3);
+	clock.start_frame_with(10.0);
+	assert_eq!(clock.take_update_steps(), 3);
+
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.add_action_primary(Action::TimeScaleAction(0.25));
+	for i in 0..3 {
+		disp.dispatch();
+	}
+	assert_eq!(disp.get_clock().get_time_scale(), 0.25);
+}
+
+#[test]
+fn test_frame_pacing() {
+	/*at 20 frames per second, five frames take at least a quarter of a second. With vsync the
+	dispatcher leaves the pacing to the renderer*/
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.set_target_fps(Some(20.0));
+	assert_eq!(disp.get_target_fps(), Some(20.0));
+	let started = Instant::now();
+	while disp.get_clock().get_time().frame < 6 {
+		disp.dispatch();
+	}
+	assert!(started.elapsed() >= Duration::from_millis(250));
+
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.set_target_fps(Some(20.0));
+	disp.add_action_primary(Action::VsyncAction(true));
+	let started = Instant::now();
+	while disp.get_clock().get_time().frame < 6 {
+		disp.dispatch();
+	}
+	assert!(disp.is_vsync());
+	assert!(started.elapsed() < Duration::from_millis(250));
+}
+
+#[test]
+fn test_bounded_game_loop() {
+	/*without a renderer and with simulated frames, a thousand frames of a second each run at
+	once, with one update per frame. Running twice in a row is the same as running once*/
+	let times = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.set_target_fps(Some(1.0));
+	disp.set_simulated_frame_time(Some(1.0));
+	disp.enter_stores(vec!(
+		Box::new(HeadlessDriver::new()),
+		Box::new(ClockStore { send_updates: false, times: times.clone() }),
+	));
+	let started = Instant::now();
+	assert!(run_frames(&mut disp, 600));
+	assert!(run_frames(&mut disp, 400));
+	assert!(started.elapsed() < Duration::from_secs(5));
+	assert_eq!(times.borrow().len(), 1000);
+	for (i, time) in times.borrow().iter().enumerate() {
+		assert_eq!(*time, GameTime { dt: 1.0, frame: i as u64 + 1, total: i as f64 + 1.0 });
+	}
+
+	// Stops at the end of the frame the predicate turns true in.
+	let seen = times.clone();
+	assert!(run_until(&mut disp, move |_| seen.borrow().len() >= 1010));
+	assert_eq!(times.borrow().len(), 1010);
+	assert!(run_until(&mut disp, |_| true));
+	assert_eq!(times.borrow().len(), 1010);
+
+	disp.add_action_primary(Action::QuitAction);
+	assert!(!run_frames(&mut disp, 10));
+
+	// Frames that run out of time count too, and a menu nobody closes doesn't hang.
+	let mut disp = Dispatcher::new(1.0 / 1000.0);
+	disp.enter_stores(vec!(Box::new(FloodStore)));
+	let frame = disp.get_clock().get_time().frame;
+	assert!(run_frames(&mut disp, 3));
+	assert_eq!(disp.get_clock().get_time().frame, frame + 3);
+	disp.add_action_primary(Action::MenuAction(MenuSubAction::PushMenuAction(0)));
+	assert!(run_frames(&mut disp, 3));
+	assert_eq!(disp.get_menu_layers().len(), 1);
+}
+
+/// Keeps the secondary queue from ever running empty.
+struct FloodStore;
+
+impl Store for FloodStore {
+	fn receive_action(&mut self, _action: &Action, ctx: &mut Context) -> StoreResult {
+		ctx.send_secondary(Action::EmptyAction);
+		ctx.send_secondary(Action::EmptyAction);
+		Ok(())
+	}
+}
+
+/// Rolls a die at the end of every frame.
+struct DiceStore {
+	rolls: Rc<RefCell<Vec<u32>>>,
+}
+
+impl Store for DiceStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::EndFrameAction => {
+				let roll = ctx.get_rng().gen_range(1, 7);
+				self.rolls.borrow_mut().push(roll);
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+}
+
+fn roll_dice(seed: u64, frames: u64) -> Vec<u32> {
+	let rolls = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new_seeded(1.0 / 60.0, seed);
+	disp.set_simulated_frame_time(Some(1.0 / 60.0));
+	disp.enter_stores(vec!(Box::new(DiceStore { rolls: rolls.clone() })));
+	run_frames(&mut disp, frames);
+	let rolls = rolls.borrow().clone();
+	return rolls;
+}
+
+#[test]
+fn test_seeded_rng() {
+	/*the same seed rolls the same numbers, another seed doesn't. Saves and recordings bring back
+	the state of the generator*/
+	assert_eq!(roll_dice(7, 50), roll_dice(7, 50));
+	assert_ne!(roll_dice(7, 50), roll_dice(8, 50));
+	assert!(roll_dice(7, 50).iter().all(|roll| *roll >= 1 && *roll <= 6));
+
+	let mut disp = Dispatcher::new_seeded(1.0 / 60.0, 7);
+	assert_eq!(disp.get_seed(), 7);
+	let save = disp.take_snapshot();
+	let first = disp.get_rng().next_u64();
+	let mut bytes = vec![];
+	save.write(&mut bytes).unwrap();
+	let save = SaveFile::read(&mut &bytes[..]).unwrap();
+	disp.restore_snapshot(&save).unwrap();
+	assert_eq!(disp.get_rng().next_u64(), first);
+
+	// Saves from before the generator was saved still load.
+	let old_save: &[u8] = b"U4XS\x01\x00\x00\x00\x00\x00\x00\x00";
+	assert_eq!(SaveFile::read(&mut &old_save[..]).unwrap().get_rng_state(), None);
+
+	let path = env::temp_dir().join("u4x_test_seeded_rng.rec");
+	let recorded = Rc::new(RefCell::new(vec![]));
+	let replayed = Rc::new(RefCell::new(vec![]));
+	{
+		let mut disp = Dispatcher::new_seeded(1.0 / 60.0, 7);
+		disp.record_to(&path).unwrap();
+		disp.enter_stores(vec!(Box::new(DiceStore { rolls: recorded.clone() })));
+		for i in 1..30 {
+			disp.dispatch();
+		}
+		disp.stop_recording();
+	}
+	{
+		let mut disp = Dispatcher::new_seeded(1.0 / 60.0, 8);
+		disp.replay_from(&path).unwrap();
+		disp.enter_stores(vec!(Box::new(DiceStore { rolls: replayed.clone() })));
+		while disp.dispatch() {};
+		assert_eq!(disp.get_replay_divergence(), None);
+	}
+	assert!(recorded.borrow().len() > 0);
+	assert_eq!(recorded, replayed);
+}
+
+#[test]
+fn test_logging() {
+	/*messages reach every sink if their level is enabled for their target, where the longest
+	matching target wins. A ring buffer only keeps the newest messages*/
+	let ring = RingBuffer::new(100);
+	log::add_sink(Box::new(ring.clone()));
+	log::set_target_level("frame::tests", Level::Debug);
+	log::set_target_level("frame::tests::quiet", Level::Error);
+
+	frame_debug!("shown {}", 1);
+	frame_trace!("hidden");
+	log::log(Level::Warn, "frame::tests::quiet", format_args!("hidden"));
+	log::log(Level::Error, "frame::tests::quiet", format_args!("shown {}", 2));
+	log::log(Level::Trace, "frame::testsuite", format_args!("hidden"));
+	frame_info!("shown {}", 3);
+
+	// Other tests may log at the same time.
+	let mine: Vec<String> = ring.get_records().iter()
+		.filter(|record| record.target.starts_with("frame::tests"))
+		.map(|record| record.to_string())
+		.collect();
+	assert_eq!(mine, vec![
+		"[DEBUG frame::tests] shown 1",
+		"[ERROR frame::tests::quiet] shown 2",
+		"[INFO frame::tests] shown 3",
+	]);
+	assert!(log::is_enabled(Level::Debug, "frame::tests::loud"));
+	assert!(!log::is_enabled(Level::Warn, "frame::tests::quiet::inner"));
+
+	let mut small = RingBuffer::new(2);
+	for level in [Level::Error, Level::Warn, Level::Info].iter() {
+		small.write(&log::Record {
+			level: *level,
+			target: String::from("frame::tests"),
+			message: String::new(),
+		});
+	}
+	let levels: Vec<Level> = small.get_records().iter().map(|record| record.level).collect();
+	assert_eq!(levels, vec![Level::Warn, Level::Info]);
+}
+
+/// Writes down every action it receives.
+struct LogStore {
+	received: Rc<RefCell<Vec<String>>>,
+}
+
+impl Store for LogStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		self.received.borrow_mut().push(encode_action(action));
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::KeyboardAction, ActionKind::MenuAction, ActionKind::QuitAction])
+	}
+}
+
+fn key(scancode: Scancode) -> Action {
+	Action::KeyboardAction(scancode)
+}
+
+fn menu(sub: MenuSubAction) -> Action {
+	Action::MenuAction(sub)
+}
+
+#[test]
+fn test_menu_parks_gameplay_actions() {
+	/*key presses sent while menus are open wait until the last one is closed and come in their
+	original order, menu actions are handled right away*/
+	let received = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enter_stores(vec!(Box::new(LogStore { received: received.clone() })));
+	disp.dispatch();
+	disp.dispatch();
+
+	disp.add_action_primary(key(Scancode::A));
+	disp.add_action_primary(menu(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(key(Scancode::B));
+	disp.add_action_primary(menu(MenuSubAction::PushMenuAction(1)));
+	disp.add_action_primary(key(Scancode::C));
+	disp.add_action_primary(menu(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(menu(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(key(Scancode::D));
+	for i in 0..7 {
+		assert!(disp.dispatch());
+	}
+
+	let expected: Vec<String> = vec![
+		key(Scancode::A),
+		menu(MenuSubAction::PushMenuAction(0)),
+		menu(MenuSubAction::PushMenuAction(1)),
+		menu(MenuSubAction::PopMenuAction),
+		menu(MenuSubAction::PopMenuAction),
+		key(Scancode::B),
+		key(Scancode::C),
+	].iter().map(encode_action).collect();
+	assert_eq!(*received.borrow(), expected);
+	assert!(disp.get_menu_layers().is_empty());
+
+	disp.dispatch();
+	assert_eq!(received.borrow().last(), Some(&encode_action(&key(Scancode::D))));
+}
+
+/// Fails on every key press.
+struct FailingStore {
+	calls: Rc<Cell<u32>>,
+}
+
+impl Store for FailingStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		self.calls.set(self.calls.get() + 1);
+		Err(String::from("broken key"))
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::KeyboardAction])
+	}
+}
+
+/// Writes down the errors of other stores, and fails on them itself.
+struct ErrorStore {
+	errors: Rc<RefCell<Vec<(StoreId, String)>>>,
+}
+
+impl Store for ErrorStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::StoreErrorAction(id, ref error) => self.errors.borrow_mut().push((id, error.clone())),
+			_ => {}
+		}
+		Err(String::from("can't handle errors either"))
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::StoreErrorAction])
+	}
+}
+
+#[test]
+fn test_store_errors() {
+	/*a failing store is reported to the other stores, and depending on the policy keeps getting
+	actions, is removed or stops the loop. Failing on an error doesn't lead to another one*/
+	let policies = [ErrorPolicy::LogAndContinue, ErrorPolicy::DisableStore, ErrorPolicy::StopLoop];
+	let expected_calls = [2, 1, 1];
+	let expected_errors = [2, 1, 0];
+	for i in 0..policies.len() {
+		let calls = Rc::new(Cell::new(0));
+		let errors = Rc::new(RefCell::new(vec![]));
+		let mut disp = Dispatcher::new(1.0);
+		disp.set_error_policy(policies[i]);
+		disp.enter_stores(vec!(
+			Box::new(FailingStore { calls: calls.clone() }),
+			Box::new(ErrorStore { errors: errors.clone() }),
+		));
+		disp.dispatch();
+		disp.dispatch();
+
+		disp.add_action_primary(key(Scancode::A));
+		disp.add_action_primary(key(Scancode::B));
+		let running: Vec<bool> = (0..5).map(|_| disp.dispatch()).collect();
+
+		assert_eq!(running.contains(&false), policies[i] == ErrorPolicy::StopLoop);
+		assert_eq!(calls.get(), expected_calls[i]);
+		assert_eq!(errors.borrow().len(), expected_errors[i]);
+		for error in errors.borrow().iter() {
+			assert_eq!(*error, (StoreId(0), String::from("broken key")));
+		}
+	}
+
+	//adding a store while receiving actions in parallel is an error, not a crash
+	let errors = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new(1.0);
+	disp.enable_parallel_dispatch(vec![ActionKind::UpdateAction], 2);
+	disp.enter_stores(vec!(
+		Box::new(ClockStore { send_updates: true, times: Rc::new(RefCell::new(vec![])) }),
+		Box::new(ErrorStore { errors: errors.clone() }),
+	));
+	disp.add_send_store(Box::new(SpawningStore));
+	for i in 0..10 {
+		assert!(disp.dispatch());
+	}
+	assert!(!errors.borrow().is_empty());
+	assert_eq!(errors.borrow()[0].1, "Stores can't add other stores while receiving actions in parallel");
+	assert_eq!(disp.get_store_ids().len(), 3);
+
+	//once a store running in parallel stops the loop, what the stores after it did is dropped
+	let mut disp = Dispatcher::new(1.0);
+	disp.set_error_policy(ErrorPolicy::StopLoop);
+	disp.enable_parallel_dispatch(vec![ActionKind::UpdateAction], 2);
+	disp.enter_stores(vec!(
+		Box::new(ClockStore { send_updates: true, times: Rc::new(RefCell::new(vec![])) }),
+	));
+	disp.add_send_store(Box::new(SpawningStore));
+	disp.add_send_store(Box::new(RemovingStore { target: StoreId(0) }));
+	let mut dispatches = 0;
+	while disp.dispatch() {
+		dispatches += 1;
+		assert!(dispatches < 10);
+	}
+	assert_eq!(disp.get_store_ids().len(), 3);
+}
+
+/// Removes another store on every update.
+struct RemovingStore {
+	target: StoreId,
+}
+
+impl Store for RemovingStore {
+	fn receive_action(&mut self, _action: &Action, ctx: &mut Context) -> StoreResult {
+		ctx.remove_store(self.target);
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::UpdateAction])
+	}
+}
+
+/// Tries to add a store on every update.
+struct SpawningStore;
+
+impl Store for SpawningStore {
+	fn receive_action(&mut self, _action: &Action, ctx: &mut Context) -> StoreResult {
+		ctx.add_store(Box::new(HeadlessDriver::new()));
+		Ok(())
+	}
+
+	fn subscriptions(&self) -> Option<Vec<ActionKind>> {
+		Some(vec![ActionKind::UpdateAction])
+	}
+}
+
+#[test]
+fn test_menu_with_empty_queue() {
+	/*a menu with nothing to do waits for input instead of running off the end of the queue*/
+	let received = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enter_stores(vec!(Box::new(LogStore { received: received.clone() })));
+	disp.dispatch();
+	disp.dispatch();
+
+	disp.add_action_primary(menu(MenuSubAction::ChangeMenuStateAction));
+	for i in 0..3 {
+		assert!(disp.dispatch());
+	}
+
+	let wait = encode_action(&menu(MenuSubAction::WaitForInputAction));
+	assert_eq!(received.borrow()[1..], [wait.clone(), wait]);
+}
+
+#[test]
+fn test_leftover_menu_actions_and_quitting() {
+	/*menu actions of a closed menu are dropped, closing a menu that isn't open does nothing, but
+	quitting works with and without a menu*/
+	let received = Rc::new(RefCell::new(vec![]));
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.enter_stores(vec!(Box::new(LogStore { received: received.clone() })));
+	disp.dispatch();
+	disp.dispatch();
+
+	disp.add_action_primary(menu(MenuSubAction::DrawAction));
+	disp.add_action_primary(menu(MenuSubAction::WaitForInputAction));
+	assert!(disp.dispatch());
+	assert!(disp.dispatch());
+	assert!(received.borrow().is_empty());
+
+	disp.add_action_primary(menu(MenuSubAction::PopMenuAction));
+	disp.add_action_primary(menu(MenuSubAction::PushMenuAction(0)));
+	disp.add_action_primary(menu(MenuSubAction::PopMenuAction));
+	disp.add_action_primary(menu(MenuSubAction::PopMenuAction));
+	for i in 0..4 {
+		assert!(disp.dispatch());
+	}
+	assert!(disp.get_menu_layers().is_empty());
+	assert_eq!(received.borrow().len(), 2);
+
+	disp.add_action_primary(menu(MenuSubAction::QuitAction));
+	assert!(!disp.dispatch());
+
+	let mut disp = Dispatcher::new(1.0 / 60.0);
+	disp.dispatch();
+	disp.dispatch();
+	disp.add_action_primary(menu(MenuSubAction::ChangeMenuStateAction));
+	disp.add_action_primary(Action::UpdateAction);
+	disp.add_action_primary(Action::QuitAction);
+	assert!(disp.dispatch());
+	assert!(!disp.dispatch());
+}
+
+/// Counts the draws it's asked for.
+struct CountingScene {
+	draws: Rc<Cell<u32>>,
+}
+
+impl Scene for CountingScene {
+	fn receive_menu_sub_action(&mut self, sub_action: &MenuSubAction) -> Vec<MenuSubAction> {
+		match sub_action {
+			MenuSubAction::DrawAction => self.draws.set(self.draws.get() + 1),
+			_ => {}
+		}
+		return vec![];
+	}
+}
+
+#[test]
+fn test_nested_scenes() {
+	/*only the scene of the topmost menu draws*/
+	let pause_draws = Rc::new(Cell::new(0));
+	let options_draws = Rc::new(Cell::new(0));
+	let mut scene_manager = SceneManager::new();
+	scene_manager.add_scenes(vec![
+		Box::new(CountingScene { draws: pause_draws.clone() }),
+		Box::new(CountingScene { draws: options_draws.clone() }),
+	]);
+	let time = GameTime { dt: 0.0, frame: 0, total: 0.0 };
+	let mut ctx = Context::new(time, StoreId(0), 1);
+
+	let draw = Action::MenuAction(MenuSubAction::DrawAction);
+	scene_manager.receive_action(&Action::MenuAction(MenuSubAction::PushMenuAction(0)), &mut ctx).unwrap();
+	scene_manager.receive_action(&draw, &mut ctx).unwrap();
+	scene_manager.receive_action(&Action::MenuAction(MenuSubAction::PushMenuAction(1)), &mut ctx).unwrap();
+	assert_eq!(scene_manager.get_current_scene(), Some(1));
+	scene_manager.receive_action(&draw, &mut ctx).unwrap();
+	scene_manager.receive_action(&draw, &mut ctx).unwrap();
+	scene_manager.receive_action(&Action::MenuAction(MenuSubAction::PopMenuAction), &mut ctx).unwrap();
+	scene_manager.receive_action(&draw, &mut ctx).unwrap();
+	scene_manager.receive_action(&Action::MenuAction(MenuSubAction::PopMenuAction), &mut ctx).unwrap();
+	assert_eq!(scene_manager.get_current_scene(), None);
+	scene_manager.receive_action(&draw, &mut ctx).unwrap();
+
+	assert_eq!(pause_draws.get(), 2);
+	assert_eq!(options_draws.get(), 2);
+}
+
+/// Follows every `A` key with a `Z` key.
+struct ExpandMiddleware;
+
+impl Middleware for ExpandMiddleware {
+	fn process(&mut self, action: Action, time: &GameTime) -> Vec<Action> {
+		match action {
+			Action::KeyboardAction(Scancode::A) => vec![action, Action::KeyboardAction(Scancode::Z)],
+			_ => vec![action],
+		}
+	}
+}
+
+/// An input source without a window: opens the menu in the third frame and, in the menu, sends
+/// the scripted input one wait after the other. Waiting with nothing left to send would block.
+struct ScriptedInputStore {
+	waits: VecDeque<Vec<Action>>,
+	clicks: Rc<RefCell<Vec<i32>>>,
+}
+
+impl Store for ScriptedInputStore {
+	fn receive_action(&mut self, action: &Action, ctx: &mut Context) -> StoreResult {
+		match action {
+			&Action::EndFrameAction => {
+				if ctx.get_time().frame == 3 {
+					ctx.send(Action::MenuAction(MenuSubAction::ChangeMenuStateAction));
+				}
+				ctx.send(Action::UpdateAction);
+			}
+			&Action::MenuAction(MenuSubAction::WaitForInputAction) => {
+				match self.waits.pop_front() {
+					Some(inputs) => {
+						for input in inputs {
+							ctx.send(input);
+						}
+					}
+					None => panic!("waited for input with nobody at the controls"),
+				}
+			}
+			&Action::MenuAction(MenuSubAction::ClickAction(x, ..)) => {
+				self.clicks.borrow_mut().push(x);
+			}
+			_ => {}
+		}
+		Ok(())
+	}
+
+	fn is_input_source(&self) -> bool {
+		true
+	}
+}
+
+#[test]
+fn test_replay_menu_visit() {
+	/*records a visit to the menu where the player clicks twice and closes it again, then replays it
+	with an input source that has nothing to send. The replay mustn't wait for input in the menu
+	and should get the same clicks*/
+	let path = env::temp_dir().join("u4x_test_replay_menu_visit.rec");
+	let recorded = Rc::new(RefCell::new(vec![]));
+	let replayed = Rc::new(RefCell::new(vec![]));
+	let click = |x| Action::MenuAction(MenuSubAction::ClickAction(x, 0, MouseButton::Left, 1));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.record_to(&path).unwrap();
+		let waits = vec![
+			vec![],
+			vec![click(4)],
+			vec![click(9), Action::MenuAction(MenuSubAction::ChangeMenuStateAction)],
+		];
+		disp.enter_stores(vec!(
+			Box::new(ScriptedInputStore { waits: waits.into_iter().collect(), clicks: recorded.clone() }),
+		));
+		for i in 1..40 {
+			disp.dispatch();
+		}
+		assert!(disp.get_menu_layers().is_empty());
+		disp.drop_stores();
+	}
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.replay_from(&path).unwrap();
+		disp.enter_stores(vec!(Box::new(ScriptedInputStore { waits: VecDeque::new(), clicks: replayed.clone() })));
+		while disp.dispatch() {};
+		assert_eq!(disp.get_replay_divergence(), None);
+	}
+
+	assert_eq!(*recorded.borrow(), vec![4, 9]);
+	assert_eq!(recorded, replayed);
+}
+
+#[test]
+fn test_middleware() {
+	/*an input store cycles through the keys A, B and C. The middleware chain drops every B, adds a Z
+	after every A and only lets the first C through*/
+	let received = Rc::new(RefCell::new(vec![]));
+
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.add_middleware(Box::new(FilterMiddleware::new(Box::new(|action| match action {
+			Action::KeyboardAction(Scancode::B) => false,
+			_ => true,
+		}))));
+		disp.add_middleware(Box::new(ExpandMiddleware));
+		disp.add_middleware(Box::new(DebounceMiddleware::new(3600.0, Box::new(|action| match action {
+			Action::KeyboardAction(Scancode::C) => true,
+			_ => false,
+		}))));
+		disp.enter_stores(vec!(
+			Box::new(InputStore { keys: vec![Scancode::A, Scancode::B, Scancode::C], received: received.clone() }),
+		));
+
+		for i in 1..30 {
+			disp.dispatch();
+		}
+	}
+
+	let received = received.borrow();
+	assert!(received.len() > 3);
+	assert!(!received.contains(&Scancode::B));
+	assert_eq!(received.iter().filter(|&&key| key == Scancode::C).count(), 1);
+	for (index, key) in received.iter().enumerate() {
+		if *key == Scancode::A {
+			assert_eq!(received.get(index + 1), Some(&Scancode::Z));
+		}
+	}
+
+	// the window is real time, so clicks in a menu, where game time stands still, get through as
+	// long as they are far enough apart
+	let mut debounce = DebounceMiddleware::clicks(0.02);
+	let in_menu = GameTime { dt: 0.0, frame: 7, total: 3.0 };
+	let click = || Action::MenuAction(MenuSubAction::ClickAction(1, 1, MouseButton::Left, 1));
+	let mut passed = vec![];
+	for _ in 0..3 {
+		passed.push(debounce.process(click(), &in_menu).len());
+		sleep(Duration::from_millis(40));
+	}
+	passed.push(debounce.process(click(), &in_menu).len());
+	passed.push(debounce.process(click(), &in_menu).len());
+	assert_eq!(passed, vec![1, 1, 1, 1, 0]);
+
+	// a replay drops the same input as the recorded run, even though its own window lets
+	// everything through
+	let path = env::temp_dir().join("u4x_test_middleware.rec");
+	let recorded = Rc::new(RefCell::new(vec![]));
+	let replayed = Rc::new(RefCell::new(vec![]));
+	let debounce_c = |window| DebounceMiddleware::new(window, Box::new(|action| match action {
+		Action::KeyboardAction(Scancode::C) => true,
+		_ => false,
+	}));
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.record_to(&path).unwrap();
+		disp.add_middleware(Box::new(debounce_c(3600.0)));
+		disp.enter_stores(vec!(
+			Box::new(InputStore { keys: vec![Scancode::A, Scancode::B, Scancode::C], received: recorded.clone() }),
+		));
+		for i in 1..30 {
+			disp.dispatch();
+		}
+		disp.drop_stores();
+	}
+	{
+		let mut disp = Dispatcher::new(1.0 / 60.0);
+		disp.replay_from(&path).unwrap();
+		disp.add_middleware(Box::new(debounce_c(0.0)));
+		disp.enter_stores(vec!(Box::new(InputStore { keys: vec![], received: replayed.clone() })));
+		while disp.dispatch() {};
+		assert_eq!(disp.get_replay_divergence(), None);
+	}
+	assert_eq!(recorded.borrow().iter().filter(|&&key| key == Scancode::C).count(), 1);
+	assert_eq!(recorded, replayed);
 }
 
 #[test]
@@ -96,6 +1495,176 @@ fn test_renderer() {
 	assert!(test_renderer.test(test_img));
 }
 
+#[test]
+fn test_headless_renderer() {
+	/* renders the test image into an offscreen framebuffer and checks the presented pixels against
+	the color space. The image is 4 pixels wide and holds the indices 0 to 15 in order.*/
+	let mut space = [Color::RGB(0, 0, 0); 15];
+	for index in 0..15 {
+		space[index] = Color::RGB(index as u8 * 16, 0x80, 0xFF - index as u8 * 16);
+	}
+
+	let mut test_renderer = Renderer::new_headless(256, 256, Some(space));
+	let mut test_img = Img::new_from_u8(0, vec![]);
+
+	match File::open("./resources/test_img.u4i") {
+		Ok(f) => {
+			test_img = Img::new_from_file(f);
+		}
+		Err(e) => {
+			print!("{}", e);
+		}
+	}
+
+	test_renderer.add_to_canvas(0, 0, test_img).unwrap();
+	test_renderer.draw_with_clear().unwrap();
+
+	let frame_buffer = test_renderer.get_backend();
+	assert_eq!(frame_buffer.get_frames_presented(), 1);
+
+	for index in 0..15u8 {
+		let x = (index as u32 % 4) * 8;
+		let y = (index as u32 / 4) * 8;
+		assert_eq!(frame_buffer.get_index(x + 7, y + 7), Some(index));
+		assert_eq!(frame_buffer.get_color(x, y), Some(space[index as usize]));
+	}
+
+	assert_eq!(frame_buffer.get_index(24, 24), None);
+	assert_eq!(frame_buffer.get_color(24, 24), Some(Color::RGB(0, 0, 0)));
+	assert_eq!(frame_buffer.get_index(32, 0), None);
+	assert_eq!(frame_buffer.get_color(32, 0), Some(Color::RGB(0, 0, 0)));
+}
+
+#[test]
+fn test_render_layers() {
+	/*images are painted by layer and z instead of the order they arrive in, and transparent pixels
+	let the ones below show through. Menu images end up on top of everything*/
+	let mut renderer = Renderer::new_headless(64, 8, None);
+	let mut ctx = Context::new(GameTime { dt: 0.0, frame: 0, total: 0.0 }, StoreId(0), 1);
+	let img = |pixels: u8| ImgHandle::new(Img::new_from_u8(2, vec![pixels]));
+	let actions = vec![
+		menu(MenuSubAction::AddImgToCanvasAction(0, 0, img(0x4F))),
+		Action::AddImgToCanvasAction(0, 0, Depth::new(Layer::Ui, 0), img(0x13)),
+		Action::AddImgToCanvasAction(2, 0, Depth::new(Layer::World, 5), img(0x22)),
+		Action::AddImgToCanvasAction(2, 0, Depth::new(Layer::World, -1), img(0x11)),
+		Action::AddImgToCanvasAction(2, 0, Depth::new(Layer::Background, 9), img(0x00)),
+		Action::AddImgToCanvasAction(4, 0, Depth::new(Layer::World, 0), img(0x55)),
+		Action::AddImgToCanvasAction(4, 0, Depth::new(Layer::World, 0), img(0x66)),
+		Action::DrawAction(true),
+	];
+	for action in actions.iter() {
+		renderer.receive_action(action, &mut ctx).unwrap();
+	}
+
+	let indices: Vec<Option<u8>> = (0..7).map(|x| renderer.get_backend().get_index(x * 8, 0)).collect();
+	assert_eq!(indices, vec![Some(4), Some(3), Some(2), Some(2), Some(6), Some(6), None]);
+}
+
+#[test]
+fn test_camera() {
+	/*the camera moves and zooms the world layers, the UI stays where it is. A shake stays within
+	its amplitude and calms down after its duration*/
+	let mut camera = Camera::new();
+	camera.set_pos((10, 5));
+	camera.set_zoom(2);
+	assert_eq!(camera.world_to_screen(12, 6), (4, 2));
+	assert_eq!(camera.screen_to_world(5, 3), (12, 6));
+	assert_eq!(camera.screen_to_world(-1, 0), (9, 5));
+
+	camera.set_zoom(1);
+	camera.center_on(100, 100, 4, 4, 32, 16);
+	assert_eq!(camera.get_pos(), (86, 94));
+
+	camera.set_shake_seed(3);
+	camera.shake(3, 1.0);
+	for i in 0..3 {
+		camera.update(0.25);
+		let (x, y) = camera.world_to_screen(86, 94);
+		assert!(x.abs() <= 3 && y.abs() <= 3);
+	}
+	camera.update(0.25);
+	assert!(!camera.is_shaking());
+	assert_eq!(camera.world_to_screen(86, 94), (0, 0));
+
+	let mut renderer = Renderer::new_headless(64, 8, None);
+	let mut ctx = Context::new(GameTime { dt: 0.0, frame: 0, total: 0.0 }, StoreId(0), 1);
+	let img = |pixels: u8| ImgHandle::new(Img::new_from_u8(2, vec![pixels]));
+	let actions = vec![
+		Action::CameraAction(CameraSubAction::MoveToAction(10, 0)),
+		Action::CameraAction(CameraSubAction::ZoomAction(2)),
+		Action::AddImgToCanvasAction(10, 0, Depth::new(Layer::World, 0), img(0x11)),
+		Action::AddImgToCanvasAction(14, 0, Depth::new(Layer::Background, 0), img(0x33)),
+		Action::AddImgToCanvasAction(6, 0, Depth::new(Layer::Ui, 0), img(0x22)),
+		Action::DrawAction(true),
+	];
+	for action in actions.iter() {
+		renderer.receive_action(action, &mut ctx).unwrap();
+	}
+
+	let indices: Vec<Option<u8>> = (0..8).map(|x| renderer.get_backend().get_index(x * 8, 0)).collect();
+	assert_eq!(indices, vec![Some(1), Some(1), Some(1), Some(1), None, None, Some(2), Some(2)]);
+
+	//shaking doesn't draw from the game's random numbers
+	let state = ctx.get_rng().get_state();
+	renderer.receive_action(&Action::CameraAction(CameraSubAction::ShakeAction(3, 1.0)), &mut ctx).unwrap();
+	renderer.receive_action(&Action::EndFrameAction, &mut ctx).unwrap();
+	assert!(renderer.get_camera().is_shaking());
+	assert_eq!(ctx.get_rng().get_state(), state);
+
+	//the shake runs on real frame time, so it ends even though no game time passes
+	ctx.frame_time = 1.0;
+	renderer.receive_action(&Action::EndFrameAction, &mut ctx).unwrap();
+	assert!(!renderer.get_camera().is_shaking());
+
+	//world positions left of and above the origin can be followed and drawn
+	let actions = vec![
+		Action::CameraAction(CameraSubAction::ZoomAction(1)),
+		Action::CameraAction(CameraSubAction::FollowAction(-20, -3, 2, 2)),
+		Action::AddImgToCanvasAction(-20, -2, Depth::new(Layer::World, 0), img(0x44)),
+		Action::DrawAction(true),
+	];
+	for action in actions.iter() {
+		renderer.receive_action(action, &mut ctx).unwrap();
+	}
+	assert_eq!(renderer.get_camera().get_pos(), (-23, -2));
+	let indices: Vec<Option<u8>> = (0..8).map(|x| renderer.get_backend().get_index(x * 8, 0)).collect();
+	assert_eq!(indices, vec![None, None, None, Some(4), Some(4), None, None, None]);
+}
+
+#[test]
+fn test_texture_cache() {
+	/*an image handle is uploaded once and reused every frame, changing the color space uploads it
+	again with the new colors, and images that aren't drawn anymore are dropped*/
+	let mut renderer = Renderer::new_headless(16, 8, None);
+	let mut ctx = Context::new(GameTime { dt: 0.0, frame: 0, total: 0.0 }, StoreId(0), 1);
+	let handle = ImgHandle::new(Img::new_from_u8(2, vec![0x1F]));
+	let copy = handle.clone();
+	assert_eq!(handle.get_id(), copy.get_id());
+	assert!(handle.get_id() != ImgHandle::new(Img::new_from_u8(2, vec![0x1F])).get_id());
+
+	for _ in 0..3 {
+		renderer.receive_action(&Action::AddImgToCanvasAction(0, 0, Depth::new(Layer::Ui, 0), handle.clone()), &mut ctx).unwrap();
+		renderer.receive_action(&Action::DrawAction(true), &mut ctx).unwrap();
+	}
+	assert_eq!(renderer.get_backend().get_texture_uploads(), 1);
+	assert_eq!(renderer.get_backend().get_index(0, 0), Some(1));
+	assert_eq!(renderer.get_backend().get_index(8, 0), None);
+
+	let mut space = [Color::RGB(0, 0, 0); 15];
+	space[1] = Color::RGB(0x12, 0x34, 0x56);
+	renderer.change_color_space(space);
+	assert_eq!(renderer.get_backend().get_texture_count(), 0);
+	renderer.receive_action(&Action::AddImgToCanvasAction(0, 0, Depth::new(Layer::Ui, 0), copy), &mut ctx).unwrap();
+	renderer.receive_action(&Action::DrawAction(true), &mut ctx).unwrap();
+	assert_eq!(renderer.get_backend().get_texture_uploads(), 2);
+	assert_eq!(renderer.get_backend().get_color(0, 0), Some(Color::RGB(0x12, 0x34, 0x56)));
+
+	for _ in 0..100 {
+		renderer.receive_action(&Action::DrawAction(true), &mut ctx).unwrap();
+	}
+	assert_eq!(renderer.get_backend().get_texture_count(), 0);
+}
+
 #[test]
 fn test_sprites_and_menus() {
 	let mut test_renderer = Renderer::new(256, 256, None);
@@ -117,7 +1686,7 @@ fn test_sprites_and_menus() {
 		}
 	}
 
-	let mut test_object = TestObj::new(test_sprite);
+	let mut test_object = TestObject::new(test_sprite);
 
 	match File::open("./resources/exit_button.u4i") {
 		Ok(f) => {
@@ -136,7 +1705,6 @@ fn test_sprites_and_menus() {
 
 	let mut test_button = Button::new(
 		button_sprite,
-		(0, 0),
 		Box::new(|| {
 			println!("Clicked button!");
 			return MenuSubAction::QuitAction;
@@ -149,11 +1717,11 @@ fn test_sprites_and_menus() {
 
 	let mut scene_manager = SceneManager::new();
 
-	scene_manager.add_scenes(vec![&mut test_scene]);
+	scene_manager.add_scenes(vec![Box::new(test_scene)]);
 
 	{
 		let mut disp = Dispatcher::new(1.0);
-		disp.enter_refs(vec!(&mut test_renderer, &mut test_object, &mut scene_manager));//, &mut test_object));
+		disp.enter_stores(vec!(Box::new(test_renderer), Box::new(test_object), Box::new(scene_manager)));
 
 		while disp.dispatch() {};
 	}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Keeps what a renderer backend made out of an image (e.g. an SDL texture), keyed by the id of
+/// its `ImgHandle`. Entries that weren't used for `max_unused_frames` frames are dropped, so
+/// images nobody draws anymore don't pile up.
+pub struct TextureCache<T> {
+	textures: HashMap<u64, (T, u64)>,
+	frame: u64,
+	max_unused_frames: u64,
+	uploads: u64,
+}
+
+impl<T> TextureCache<T> {
+	pub fn new(max_unused_frames: u64) -> Self {
+		Self {
+			textures: HashMap::new(),
+			frame: 0,
+			max_unused_frames,
+			uploads: 0,
+		}
+	}
+
+	/// Returns the entry for an image, calling `upload` to make it if there is none yet.
+	pub fn get_or_upload<F>(&mut self, id: u64, upload: F) -> Result<&T, String>
+		where F: FnOnce() -> Result<T, String> {
+		let frame = self.frame;
+		match self.textures.entry(id) {
+			Entry::Occupied(entry) => {
+				let entry = entry.into_mut();
+				entry.1 = frame;
+				Ok(&entry.0)
+			}
+			Entry::Vacant(entry) => {
+				let texture = upload()?;
+				self.uploads += 1;
+				Ok(&entry.insert((texture, frame)).0)
+			}
+		}
+	}
+
+	/// Counts a frame and removes the entries that went unused for too long. Backends call this
+	/// when presenting. Returns the removed entries, for backends that have to free them by hand.
+	pub fn end_frame(&mut self) -> Vec<T> {
+		self.frame += 1;
+		let frame = self.frame;
+		let max_unused_frames = self.max_unused_frames;
+		let expired: Vec<u64> = self.textures.iter()
+			.filter(|&(_, entry)| frame - entry.1 > max_unused_frames)
+			.map(|(&id, _)| id)
+			.collect();
+		expired.into_iter().filter_map(|id| self.textures.remove(&id)).map(|entry| entry.0).collect()
+	}
+
+	/// Removes all entries, e.g. because the color space changed, and returns them.
+	pub fn clear(&mut self) -> Vec<T> {
+		self.textures.drain().map(|(_, entry)| entry.0).collect()
+	}
+
+	/// Returns the number of images kept.
+	pub fn len(&self) -> usize {
+		self.textures.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.textures.is_empty()
+	}
+
+	/// Returns how many times `upload` was called in total.
+	pub fn get_uploads(&self) -> u64 {
+		self.uploads
+	}
+}
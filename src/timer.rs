@@ -0,0 +1,163 @@
+use super::action::Action;
+use super::clock::GameTime;
+use super::store::StoreId;
+
+/// Handle of a scheduled action, used to cancel it.
+///
+/// Every store numbers its own timers, so the ids don't depend on the order stores run in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct TimerId {
+	/// The store that started the timer, `None` if it was started on the dispatcher.
+	pub owner: Option<StoreId>,
+	pub seq: u64,
+}
+
+/// The shortest interval of a repeating timer, in seconds. Shorter ones (and ones that aren't a
+/// number) are stretched to this.
+pub const MIN_INTERVAL: f64 = 0.001;
+
+/// How often a repeating timer fires in one frame at most. If more intervals passed, e.g. in a
+/// very long frame, the rest are skipped and the timer fires again one interval later.
+pub const MAX_CATCH_UP: u32 = 10;
+
+/// When a timer fires.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Due {
+	/// Once the game time (`GameTime::total`) reaches the given second.
+	Time(f64),
+	/// At the start of the given frame.
+	Frame(u64),
+}
+
+impl Due {
+	fn is_due(&self, time: &GameTime) -> bool {
+		match *self {
+			Due::Time(total) => time.total >= total,
+			Due::Frame(frame) => time.frame >= frame,
+		}
+	}
+}
+
+enum Payload {
+	Once(Action),
+	Every(f64, Box<FnMut() -> Action + Send>),
+	// Actions can't be cloned, so a repeating timer builds a new one every time it fires.
+}
+
+/// An action waiting to be dispatched later.
+pub struct Timer {
+	id: TimerId,
+	due: Due,
+	payload: Payload,
+}
+
+impl Timer {
+	/// A timer that sends `action` once.
+	pub fn once(id: TimerId, due: Due, action: Action) -> Self {
+		Self {
+			id,
+			due,
+			payload: Payload::Once(action),
+		}
+	}
+
+	/// A timer that fires at `first` (game time in seconds) and then every `interval` seconds,
+	/// sending whatever `make` returns, until it's cancelled. The interval is at least
+	/// `MIN_INTERVAL`.
+	pub fn every(id: TimerId, first: f64, interval: f64, make: Box<FnMut() -> Action + Send>) -> Self {
+		Self {
+			id,
+			due: Due::Time(first),
+			payload: Payload::Every(interval.max(MIN_INTERVAL), make),
+		}
+	}
+
+	pub fn get_id(&self) -> TimerId {
+		self.id
+	}
+
+	pub fn get_due(&self) -> Due {
+		self.due
+	}
+}
+
+/// All timers of a dispatcher. Timers only see game time and frames, which both stand still in
+/// menus, so timers are paused there as well.
+pub struct Timers {
+	timers: Vec<Timer>,
+}
+
+impl Timers {
+	pub fn new() -> Self {
+		Self {
+			timers: vec![],
+		}
+	}
+
+	pub fn add(&mut self, timer: Timer) {
+		self.timers.push(timer);
+	}
+
+	/// Cancels a timer. Returns false if there was none with that id, e.g. because it already
+	/// fired.
+	pub fn cancel(&mut self, id: TimerId) -> bool {
+		let count = self.timers.len();
+		self.timers.retain(|timer| timer.id != id);
+		return self.timers.len() != count;
+	}
+
+	/// Returns the number of timers waiting to fire.
+	pub fn len(&self) -> usize {
+		self.timers.len()
+	}
+
+	/// Returns the actions of all timers that are due at `time`, in the order the timers were
+	/// added. One-shot timers are removed, repeating timers are moved on by their interval (once
+	/// for every interval that passed, up to `MAX_CATCH_UP` times).
+	pub fn fire(&mut self, time: &GameTime) -> Vec<Action> {
+		let mut out_vec = vec![];
+		let mut index = 0;
+		while index < self.timers.len() {
+			if !self.timers[index].due.is_due(time) {
+				index += 1;
+				continue;
+			}
+			let repeating = match self.timers[index].payload {
+				Payload::Once(_) => false,
+				Payload::Every(..) => true,
+			};
+			if !repeating {
+				match self.timers.remove(index).payload {
+					Payload::Once(action) => out_vec.push(action),
+					Payload::Every(..) => {}
+				}
+				continue;
+			}
+
+			let timer = &mut self.timers[index];
+			match timer.payload {
+				Payload::Every(interval, ref mut make) => {
+					let mut fired = 0;
+					while timer.due.is_due(time) && fired < MAX_CATCH_UP {
+						out_vec.push(make());
+						fired += 1;
+						timer.due = match timer.due {
+							Due::Time(total) => Due::Time(total + interval),
+							Due::Frame(frame) => Due::Frame(frame + 1),
+						};
+					}
+					if timer.due.is_due(time) {
+						// Skip the intervals that didn't fit into this frame and start over from now.
+						timer.due = match timer.due {
+							Due::Time(_) => Due::Time(time.total + interval),
+							Due::Frame(_) => Due::Frame(time.frame + 1),
+						};
+					}
+				}
+				Payload::Once(_) => {}
+			}
+			index += 1;
+		}
+		return out_vec;
+	}
+}